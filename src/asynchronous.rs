@@ -0,0 +1,88 @@
+use crate::{ConfigFileError, ConfigFormat};
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+/// Load a @C from the configuration file located at @path without blocking the executor.
+///
+/// This behaves like [`FromConfigFile::from_config_file`](crate::FromConfigFile::from_config_file),
+/// but reads the file using tokio's asynchronous file IO.
+///
+/// ```rust,no_run
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     host: String,
+/// }
+///
+/// # async fn load() {
+/// let config: Config = config_file::from_config_file_async("/etc/myconfig.toml")
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub async fn from_config_file_async<C, P>(path: P) -> Result<C, ConfigFileError>
+where
+    C: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let format = ConfigFormat::from_path(path).ok_or(ConfigFileError::UnsupportedFormat)?;
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(ConfigFileError::FileAccess)?;
+    format
+        .deserialize(&contents)
+        .map_err(|err| err.with_path(path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct TestConfig {
+        host: String,
+        port: u64,
+    }
+
+    #[tokio::test]
+    async fn test_unknown() {
+        let config = from_config_file_async::<TestConfig, _>("/tmp/foobar").await;
+        assert!(matches!(config, Err(ConfigFileError::UnsupportedFormat)));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "toml")]
+    async fn test_file_not_found() {
+        let config = from_config_file_async::<TestConfig, _>("/tmp/foobar.toml").await;
+        assert!(matches!(config, Err(ConfigFileError::FileAccess(_))));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "json")]
+    async fn test_json() {
+        let config: TestConfig = from_config_file_async("testdata/config.json")
+            .await
+            .unwrap();
+        assert_eq!(config.host, "example.com");
+        assert_eq!(config.port, 443);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "toml")]
+    async fn test_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "host = \"example.com\"\nport = \"443\"\n").unwrap();
+        match from_config_file_async::<TestConfig, _>(&path).await {
+            Err(ConfigFileError::Parse(err)) => {
+                assert_eq!(err.path(), Some(path.as_path()));
+                assert_eq!(err.key_path(), Some("port"));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Crash-safe file replacement.
+//!
+//! The new contents are written to a temporary file living in the same directory as the target,
+//! flushed to disk and then renamed over the target, so that readers either see the old file or
+//! the new one, never a truncated one.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The steps of an atomic write, after which a failure can be simulated in tests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// The temporary file has been created
+    Created,
+    /// The contents have been written to the temporary file
+    Written,
+    /// The temporary file has been flushed to disk
+    Synced,
+}
+
+/// Atomically replace the file located at @path with @contents
+pub(crate) fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_with(path, contents, |_| Ok(()))
+}
+
+pub(crate) fn write_with<F>(path: &Path, contents: &[u8], mut hook: F) -> io::Result<()>
+where
+    F: FnMut(Step) -> io::Result<()>,
+{
+    // Replace the file a symlink points to rather than the symlink itself
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err),
+    };
+    let original = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    let (tmp_path, file) = create_temp(&path)?;
+    let res = (|| {
+        hook(Step::Created)?;
+        if let Some(original) = &original {
+            copy_metadata(&file, original)?;
+        }
+        (&file).write_all(contents)?;
+        hook(Step::Written)?;
+        file.sync_all()?;
+        hook(Step::Synced)?;
+        drop(file);
+        fs::rename(&tmp_path, &path)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return res;
+    }
+    sync_parent(&path);
+    Ok(())
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?
+        .to_string_lossy();
+    loop {
+        let tmp_path = parent(path).join(format!(
+            ".{}.{}.{}.tmp",
+            file_name,
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+        {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn copy_metadata(file: &File, original: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+
+    // SAFETY: the descriptor belongs to @file, which stays open for the whole call, and fchown
+    // doesn't touch any memory of ours
+    if unsafe { libc::fchown(file.as_raw_fd(), original.uid(), original.gid()) } == -1 {
+        let err = io::Error::last_os_error();
+        // Changing the owner requires privileges we usually don't have, only try our best
+        if err.raw_os_error() != Some(libc::EPERM) {
+            return Err(err);
+        }
+    }
+    file.set_permissions(original.permissions())
+}
+
+#[cfg(not(unix))]
+fn copy_metadata(file: &File, original: &fs::Metadata) -> io::Result<()> {
+    file.set_permissions(original.permissions())
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) {
+    // The rename already happened, failing to persist it is not worth reporting
+    if let Ok(dir) = File::open(parent(path)) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut entries = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_write_new() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        write(&path, b"answer = 42\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"answer = 42\n");
+        assert_eq!(entries(dir.path()), vec!["config.toml"]);
+    }
+
+    #[test]
+    fn test_write_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, b"answer = 41\n").unwrap();
+        write(&path, b"answer = 42\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"answer = 42\n");
+        assert_eq!(entries(dir.path()), vec!["config.toml"]);
+    }
+
+    #[test]
+    fn test_failures_keep_original() {
+        for step in [Step::Created, Step::Written, Step::Synced] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("config.toml");
+            fs::write(&path, b"answer = 41\n").unwrap();
+            let res = write_with(&path, b"answer = 42\n", |current| {
+                if current == step {
+                    Err(io::Error::new(io::ErrorKind::Other, "simulated crash"))
+                } else {
+                    Ok(())
+                }
+            });
+            assert!(res.is_err());
+            assert_eq!(fs::read(&path).unwrap(), b"answer = 41\n");
+            assert_eq!(entries(dir.path()), vec!["config.toml"]);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_keep_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, b"answer = 41\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write(&path, b"answer = 42\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_follow_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let link = dir.path().join("link.toml");
+        fs::write(&path, b"answer = 41\n").unwrap();
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        write(&link, b"answer = 42\n").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&path).unwrap(), b"answer = 42\n");
+    }
+}
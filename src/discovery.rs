@@ -0,0 +1,327 @@
+use crate::{ConfigFileError, ConfigFormat, ConfigLoader, FromConfigFile};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+/// What to do when files with the same stem exist in several formats in the same directory,
+/// like `config.toml` and `config.yaml`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AmbiguityPolicy {
+    /// Fail with [`ConfigFileError::Ambiguous`]
+    Error,
+    /// Pick the file whose format comes first in this list, the formats which are not listed
+    /// coming last
+    Prefer(Vec<ConfigFormat>),
+    /// Merge all the files, in the order of [`ConfigFormat::ALL`], the last ones overriding the
+    /// first ones
+    Merge,
+}
+
+impl Default for AmbiguityPolicy {
+    fn default() -> Self {
+        Self::Error
+    }
+}
+
+/// Find the configuration file of an application in the standard locations.
+///
+/// The directories are searched in this order, as per the XDG base directory specification:
+/// - `$XDG_CONFIG_HOME/<app>`, defaulting to `$HOME/.config/<app>`
+/// - `<dir>/<app>` for each entry of `$XDG_CONFIG_DIRS`, defaulting to `/etc/xdg/<app>`
+/// - `/etc/<app>`
+///
+/// In each of them, a file named after the stem ("config" by default) with the extension of any
+/// enabled format is looked for. Finding several of them in the same directory is an error
+/// unless another [`AmbiguityPolicy`] is set.
+///
+/// ```rust,no_run
+/// use config_file::ConfigDiscovery;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     host: String,
+/// }
+///
+/// let config: Config = ConfigDiscovery::new("myapp").load().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConfigDiscovery {
+    app: String,
+    stem: String,
+    policy: AmbiguityPolicy,
+    vars: Option<HashMap<String, String>>,
+}
+
+impl ConfigDiscovery {
+    /// Look for the configuration of the application named @app
+    pub fn new<S: Into<String>>(app: S) -> Self {
+        Self {
+            app: app.into(),
+            stem: "config".to_string(),
+            policy: AmbiguityPolicy::default(),
+            vars: None,
+        }
+    }
+
+    /// Look for files named @stem instead of "config", followed by a supported extension
+    pub fn stem<S: Into<String>>(mut self, stem: S) -> Self {
+        self.stem = stem.into();
+        self
+    }
+
+    /// Use @policy when several files with the stem exist in the same directory
+    pub fn ambiguity(mut self, policy: AmbiguityPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Read `XDG_CONFIG_HOME`, `XDG_CONFIG_DIRS` and `HOME` from @vars instead of the process
+    /// environment
+    pub fn env_vars<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.vars = Some(
+            vars.into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        );
+        self
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        match &self.vars {
+            Some(vars) => vars.get(name).cloned(),
+            None => env::var(name).ok(),
+        }
+    }
+
+    /// The directory in the variable @name, empty and relative paths being ignored
+    fn dir_var(&self, name: &str) -> Option<PathBuf> {
+        self.var(name)
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+    }
+
+    /// The directories to search, the ones with the highest precedence first
+    pub fn search_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Some(config_home) = self.dir_var("XDG_CONFIG_HOME") {
+            dirs.push(config_home);
+        } else if let Some(home) = self.dir_var("HOME") {
+            dirs.push(home.join(".config"));
+        } else if let Some(app_data) = self.dir_var("APPDATA").filter(|_| cfg!(windows)) {
+            dirs.push(app_data);
+        }
+        // Each entry is checked on its own, a relative one doesn't invalidate the others
+        let config_dirs = self
+            .var("XDG_CONFIG_DIRS")
+            .unwrap_or_default()
+            .split(':')
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .collect::<Vec<_>>();
+        if !config_dirs.is_empty() {
+            dirs.extend(config_dirs);
+        } else if cfg!(unix) {
+            dirs.push(PathBuf::from("/etc/xdg"));
+        }
+        if cfg!(unix) {
+            dirs.push(PathBuf::from("/etc"));
+        }
+        dirs.into_iter().map(|dir| dir.join(&self.app)).collect()
+    }
+
+    fn candidates(&self, dir: &Path) -> Vec<PathBuf> {
+        ConfigFormat::ALL
+            .iter()
+            .flat_map(|format| format.extensions())
+            .map(|extension| dir.join(format!("{}.{}", self.stem, extension)))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// All the configuration files which exist, the ones with the highest precedence first
+    pub fn find_all(&self) -> Vec<PathBuf> {
+        self.search_dirs()
+            .iter()
+            .flat_map(|dir| self.candidates(dir))
+            .collect()
+    }
+
+    /// The files to load from the directory with the highest precedence containing any: a single
+    /// one unless the policy is [`AmbiguityPolicy::Merge`], or none if there is no file at all
+    pub fn find(&self) -> Result<Vec<PathBuf>, ConfigFileError> {
+        let mut candidates = match self
+            .search_dirs()
+            .iter()
+            .map(|dir| self.candidates(dir))
+            .find(|candidates| !candidates.is_empty())
+        {
+            Some(candidates) => candidates,
+            None => return Ok(Vec::new()),
+        };
+        if candidates.len() == 1 {
+            return Ok(candidates);
+        }
+        match &self.policy {
+            AmbiguityPolicy::Error => Err(ConfigFileError::Ambiguous(candidates)),
+            AmbiguityPolicy::Prefer(formats) => {
+                candidates.sort_by_key(|path| {
+                    ConfigFormat::from_path(path)
+                        .and_then(|format| formats.iter().position(|f| *f == format))
+                        .unwrap_or(formats.len())
+                });
+                candidates.truncate(1);
+                Ok(candidates)
+            }
+            AmbiguityPolicy::Merge => Ok(candidates),
+        }
+    }
+
+    /// Load the configuration file with the highest precedence
+    pub fn load<C: DeserializeOwned>(&self) -> Result<C, ConfigFileError> {
+        let mut files = self.find()?;
+        match files.len() {
+            0 => Err(ConfigFileError::NotFound(self.search_dirs())),
+            1 => C::from_config_file(files.remove(0)),
+            _ => files
+                .into_iter()
+                .fold(ConfigLoader::new(), ConfigLoader::file)
+                .load(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "toml"))]
+mod test {
+    use super::*;
+
+    use serde::Deserialize;
+    use std::fs;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct TestConfig {
+        answer: u8,
+    }
+
+    const APP: &str = "config-file-discovery-test";
+
+    fn write(dir: &Path, file_name: &str, contents: &str) -> PathBuf {
+        let dir = dir.join(APP);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(file_name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_search_dirs() {
+        let discovery = ConfigDiscovery::new(APP).env_vars([
+            ("HOME", "/home/user"),
+            ("XDG_CONFIG_DIRS", "relative:/usr/share::/opt/etc"),
+        ]);
+        assert_eq!(
+            discovery.search_dirs(),
+            ["/home/user/.config", "/usr/share", "/opt/etc", "/etc"]
+                .iter()
+                .map(|dir| Path::new(dir).join(APP))
+                .collect::<Vec<_>>()
+        );
+        let discovery = ConfigDiscovery::new(APP)
+            .env_vars([("XDG_CONFIG_HOME", "/xdg"), ("XDG_CONFIG_DIRS", "relative")]);
+        assert_eq!(
+            discovery.search_dirs(),
+            ["/xdg", "/etc/xdg", "/etc"]
+                .iter()
+                .map(|dir| Path::new(dir).join(APP))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let home = tempfile::tempdir().unwrap();
+        let system = tempfile::tempdir().unwrap();
+        let system_config = write(system.path(), "config.toml", "answer = 41");
+        let discovery = ConfigDiscovery::new(APP).env_vars([
+            ("XDG_CONFIG_HOME", home.path().to_str().unwrap()),
+            ("XDG_CONFIG_DIRS", system.path().to_str().unwrap()),
+        ]);
+        assert_eq!(discovery.find().unwrap(), vec![system_config.clone()]);
+        assert_eq!(discovery.load::<TestConfig>().unwrap().answer, 41);
+
+        let user_config = write(home.path(), "config.toml", "answer = 42");
+        write(home.path(), "other.toml", "answer = 43");
+        assert_eq!(discovery.find_all(), vec![user_config, system_config]);
+        assert_eq!(discovery.load::<TestConfig>().unwrap().answer, 42);
+        assert_eq!(
+            discovery.stem("other").load::<TestConfig>().unwrap().answer,
+            43
+        );
+    }
+
+    #[test]
+    fn test_not_found() {
+        let home = tempfile::tempdir().unwrap();
+        let discovery = ConfigDiscovery::new(APP).env_vars([
+            ("XDG_CONFIG_HOME", home.path().to_str().unwrap()),
+            ("XDG_CONFIG_DIRS", home.path().to_str().unwrap()),
+        ]);
+        assert!(discovery.find().unwrap().is_empty());
+        assert!(matches!(
+            discovery.load::<TestConfig>(),
+            Err(ConfigFileError::NotFound(dirs)) if dirs.len() == 3
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn test_ambiguity() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct MergedConfig {
+            answer: u8,
+            question: String,
+        }
+
+        let home = tempfile::tempdir().unwrap();
+        let system = tempfile::tempdir().unwrap();
+        write(system.path(), "config.toml", "answer = 40");
+        let toml = write(home.path(), "config.toml", "answer = 41");
+        let yaml = write(home.path(), "config.yml", "answer: 42\nquestion: why");
+        let discovery = ConfigDiscovery::new(APP).env_vars([
+            ("XDG_CONFIG_HOME", home.path().to_str().unwrap()),
+            ("XDG_CONFIG_DIRS", system.path().to_str().unwrap()),
+        ]);
+        assert_eq!(discovery.find_all().len(), 3);
+        match discovery.load::<TestConfig>() {
+            Err(ConfigFileError::Ambiguous(paths)) => {
+                assert_eq!(paths, vec![toml.clone(), yaml.clone()])
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let discovery = discovery.ambiguity(AmbiguityPolicy::Prefer(vec![ConfigFormat::Yaml]));
+        assert_eq!(discovery.find().unwrap(), vec![yaml]);
+        assert_eq!(discovery.load::<TestConfig>().unwrap().answer, 42);
+
+        let discovery = discovery.ambiguity(AmbiguityPolicy::Prefer(Vec::new()));
+        assert_eq!(discovery.find().unwrap(), vec![toml]);
+        assert_eq!(discovery.load::<TestConfig>().unwrap().answer, 41);
+
+        let discovery = discovery.ambiguity(AmbiguityPolicy::Merge);
+        assert_eq!(
+            discovery.load::<MergedConfig>().unwrap(),
+            MergedConfig {
+                answer: 42,
+                question: "why".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::ConfigFileError;
+use serde::{Deserialize, Deserializer};
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// An error which occurred while parsing the contents of a configuration file, along with where
+/// it happened.
+#[derive(Debug)]
+pub struct ParseError {
+    error: ConfigFileError,
+    path: Option<PathBuf>,
+    key_path: Option<String>,
+    location: Option<(usize, usize)>,
+    snippet: Option<String>,
+}
+
+impl ParseError {
+    pub(crate) fn new(error: ConfigFileError, contents: &str) -> Self {
+        let location = location(&error);
+        let snippet = location.and_then(|(line, column)| snippet(contents, line, column));
+        Self {
+            error,
+            path: None,
+            key_path: None,
+            location,
+            snippet,
+        }
+    }
+
+    pub(crate) fn set_path(&mut self, path: &Path) {
+        self.path = Some(path.to_path_buf());
+    }
+
+    pub(crate) fn with_key_path(mut self, key_path: Option<String>) -> Self {
+        self.key_path = key_path;
+        self
+    }
+
+    /// The underlying error, as reported by the format specific parser
+    pub fn error(&self) -> &ConfigFileError {
+        &self.error
+    }
+
+    /// The path of the file which failed to parse, if it came from a file
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The path of the key whose value failed to deserialize, such as `inner.answer` or
+    /// `tags[1]`, if the error is not a syntax error
+    pub fn key_path(&self) -> Option<&str> {
+        self.key_path.as_deref()
+    }
+
+    /// The line where the error occurred, starting from 1
+    pub fn line(&self) -> Option<usize> {
+        self.location.map(|(line, _)| line)
+    }
+
+    /// The column where the error occurred, starting from 1
+    pub fn column(&self) -> Option<usize> {
+        self.location.map(|(_, column)| column)
+    }
+
+    /// The offending line, with a caret pointing at the column where the error occurred
+    pub fn snippet(&self) -> Option<&str> {
+        self.snippet.as_deref()
+    }
+
+    /// The message of the format specific parser
+    pub fn message(&self) -> String {
+        match self.error.source() {
+            Some(source) => source.to_string(),
+            None => self.error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(path) = &self.path {
+            write!(f, " {}", path.display())?;
+        }
+        if let Some((line, column)) = self.location {
+            write!(f, " at line {}, column {}", line, column)?;
+        }
+        if let Some(key_path) = &self.key_path {
+            write!(f, ": {}", key_path)?;
+        }
+        if self.error.source().is_some() {
+            write!(f, ": {}", self.message())?;
+        }
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n{}", snippet)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        // The error of the format specific parser, custom formats being wrapped only once
+        Some(self.error.source().unwrap_or(&self.error))
+    }
+}
+
+/// Deserialize a @C out of @deserializer, keeping track of the path of the key which failed
+pub(crate) fn deserialize_tracked<'de, C, D>(
+    deserializer: D,
+) -> Result<C, (D::Error, Option<String>)>
+where
+    C: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let key_path = err.path().to_string();
+        // The root of the document is displayed as "."
+        let key_path = if key_path == "." {
+            None
+        } else {
+            Some(key_path)
+        };
+        (err.into_inner(), key_path)
+    })
+}
+
+#[allow(unused)]
+fn location(error: &ConfigFileError) -> Option<(usize, usize)> {
+    let (line, column): (usize, usize) = match error {
+        #[cfg(feature = "json")]
+        ConfigFileError::Json(err) => Some((err.line(), err.column())),
+        #[cfg(feature = "toml")]
+        ConfigFileError::Toml(err) => err.line_col().map(|(line, column)| (line + 1, column + 1)),
+        #[cfg(feature = "xml")]
+        ConfigFileError::Xml(serde_xml_rs::Error::Syntax { source }) => {
+            use xml::common::Position;
+
+            let position = source.position();
+            Some((position.row as usize + 1, position.column as usize + 1))
+        }
+        #[cfg(feature = "yaml")]
+        ConfigFileError::Yaml(err) => err
+            .location()
+            .map(|location| (location.line(), location.column())),
+        _ => None,
+    }?;
+    // Some parsers report errors without a position as being on line 0
+    if line == 0 {
+        None
+    } else {
+        Some((line, column.max(1)))
+    }
+}
+
+/// Render the line @line of @contents with a caret under @column
+fn snippet(contents: &str, line: usize, column: usize) -> Option<String> {
+    let text = contents.lines().nth(line - 1)?.trim_end();
+    let number = line.to_string();
+    let gutter = " ".repeat(number.len());
+    // Keep tabs so that the caret stays aligned
+    let padding = text
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    Some(format!(
+        "{} |\n{} | {}\n{} | {}^",
+        gutter, number, text, gutter, padding
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(
+            snippet("host = \"example.com\"\nport = \"443\"\n", 2, 8).unwrap(),
+            "  |\n2 | port = \"443\"\n  |        ^"
+        );
+        assert_eq!(
+            snippet("a\nb\nc\nd\ne\nf\ng\nh\ni\n\tj = k", 10, 3).unwrap(),
+            "   |\n10 | \tj = k\n   | \t ^"
+        );
+        assert!(snippet("a", 2, 1).is_none());
+    }
+}
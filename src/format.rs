@@ -0,0 +1,270 @@
+#[allow(unused_imports)]
+use crate::error::deserialize_tracked;
+use crate::{ConfigFileError, ParseError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{ffi::OsStr, fmt, path::Path};
+#[cfg(feature = "toml")]
+use toml_crate as toml;
+
+/// The configuration file formats this crate knows how to handle.
+/// Only the ones enabled through cargo features are available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ConfigFormat {
+    #[cfg(feature = "json")]
+    /// JSON, using serde_json
+    Json,
+    #[cfg(feature = "toml")]
+    /// TOML, using toml
+    Toml,
+    #[cfg(feature = "xml")]
+    /// XML, using serde-xml-rs
+    Xml,
+    #[cfg(feature = "yaml")]
+    /// YAML, using serde_yaml
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// All the enabled formats
+    pub const ALL: &'static [Self] = &[
+        #[cfg(feature = "json")]
+        Self::Json,
+        #[cfg(feature = "toml")]
+        Self::Toml,
+        #[cfg(feature = "yaml")]
+        Self::Yaml,
+        #[cfg(feature = "xml")]
+        Self::Xml,
+    ];
+
+    /// Guess the format of the file located at @path from its extension
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        path.as_ref()
+            .extension()
+            .and_then(OsStr::to_str)
+            .and_then(Self::from_extension)
+    }
+
+    /// Find the format associated with @extension, case insensitively
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            #[cfg(feature = "json")]
+            "json" => Some(Self::Json),
+            #[cfg(feature = "toml")]
+            "toml" => Some(Self::Toml),
+            #[cfg(feature = "xml")]
+            "xml" => Some(Self::Xml),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// The file extensions associated with this format, the preferred one first
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature = "json")]
+            Self::Json => &["json"],
+            #[cfg(feature = "toml")]
+            Self::Toml => &["toml"],
+            #[cfg(feature = "xml")]
+            Self::Xml => &["xml"],
+            #[cfg(feature = "yaml")]
+            Self::Yaml => &["yaml", "yml"],
+        }
+    }
+
+    /// The enabled formats, the most likely ones for @contents first
+    pub(crate) fn detect(contents: &str) -> Vec<Self> {
+        let contents = contents.trim_start_matches('\u{feff}').trim_start();
+        let first_line = contents.lines().next().unwrap_or_default().trim_end();
+        let mut likely = Vec::new();
+        if contents.starts_with('<') {
+            likely.push("xml");
+        } else if contents.starts_with('{') {
+            likely.extend(["json", "yaml"]);
+        } else if contents.starts_with('[') {
+            if is_toml_header(first_line) {
+                likely.extend(["toml", "json", "yaml"]);
+            } else {
+                likely.extend(["json", "yaml", "toml"]);
+            }
+        } else if contents.starts_with("---") {
+            likely.push("yaml");
+        } else if contents.lines().any(is_toml_key_value) {
+            likely.extend(["toml", "yaml"]);
+        } else {
+            likely.extend(["yaml", "toml"]);
+        }
+        let mut formats = likely
+            .into_iter()
+            .filter_map(Self::from_extension)
+            .collect::<Vec<_>>();
+        for format in Self::ALL {
+            if !formats.contains(format) {
+                formats.push(*format);
+            }
+        }
+        formats
+    }
+
+    /// Whether the format only has strings, which must then be converted to the expected types
+    pub(crate) fn is_untyped(self) -> bool {
+        #[cfg(feature = "xml")]
+        return self == Self::Xml;
+        #[cfg(not(feature = "xml"))]
+        false
+    }
+
+    #[allow(unused)]
+    pub(crate) fn deserialize<C: DeserializeOwned>(
+        self,
+        contents: &str,
+    ) -> Result<C, ConfigFileError> {
+        let res: Result<C, (ConfigFileError, Option<String>)> = match self {
+            #[cfg(feature = "json")]
+            Self::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(contents);
+                deserialize_tracked(&mut deserializer)
+                    .and_then(|config| {
+                        deserializer.end().map_err(|err| (err, None))?;
+                        Ok(config)
+                    })
+                    .map_err(|(err, key_path)| (ConfigFileError::Json(err), key_path))
+            }
+            #[cfg(feature = "toml")]
+            Self::Toml => deserialize_tracked(&mut toml::Deserializer::new(contents))
+                .map_err(|(err, key_path)| (ConfigFileError::Toml(err), key_path)),
+            #[cfg(feature = "xml")]
+            Self::Xml => crate::value::with_xml(|| {
+                deserialize_tracked(&mut serde_xml_rs::Deserializer::new_from_reader(
+                    contents.as_bytes(),
+                ))
+            })
+            .map_err(|(err, key_path)| (ConfigFileError::Xml(err), key_path)),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => deserialize_tracked(serde_yaml::Deserializer::from_str(contents))
+                .map_err(|(err, key_path)| (ConfigFileError::Yaml(err), key_path)),
+        };
+        res.map_err(|(err, key_path)| {
+            ParseError::new(err, contents)
+                .with_key_path(key_path)
+                .into()
+        })
+    }
+
+    #[allow(unused)]
+    pub(crate) fn serialize<C: Serialize>(self, value: &C) -> Result<Vec<u8>, ConfigFileError> {
+        match self {
+            #[cfg(feature = "json")]
+            Self::Json => serde_json::to_vec_pretty(value).map_err(ConfigFileError::JsonSerialize),
+            #[cfg(feature = "toml")]
+            Self::Toml => crate::value::with_toml(|| toml::to_string(value))
+                .map(String::into_bytes)
+                .map_err(ConfigFileError::TomlSerialize),
+            #[cfg(feature = "xml")]
+            Self::Xml => serde_xml_rs::to_string(value)
+                .map(String::into_bytes)
+                .map_err(ConfigFileError::XmlSerialize),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => serde_yaml::to_vec(value).map_err(ConfigFileError::YamlSerialize),
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    #[allow(unused)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            #[cfg(feature = "json")]
+            Self::Json => "JSON",
+            #[cfg(feature = "toml")]
+            Self::Toml => "TOML",
+            #[cfg(feature = "xml")]
+            Self::Xml => "XML",
+            #[cfg(feature = "yaml")]
+            Self::Yaml => "YAML",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Whether @line looks like a TOML table header such as `[server]` or `[[servers]]`
+fn is_toml_header(line: &str) -> bool {
+    let name = line.trim_start_matches('[').trim_end_matches(']');
+    line.starts_with('[')
+        && line.ends_with(']')
+        && !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '"' | '\'' | ' '))
+}
+
+/// Whether @line looks like a TOML `key = value` pair
+fn is_toml_key_value(line: &str) -> bool {
+    match line.split_once('=') {
+        Some((key, value)) => {
+            let key = key.trim();
+            !key.is_empty()
+                && !value.trim().is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '"' | '\''))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_toml_patterns() {
+        assert!(is_toml_header("[server]"));
+        assert!(is_toml_header("[[servers]]"));
+        assert!(!is_toml_header("[1, 2]"));
+        assert!(is_toml_key_value("host = \"example.com\""));
+        assert!(is_toml_key_value("inner.answer=42"));
+        assert!(!is_toml_key_value("host: example.com"));
+        assert!(!is_toml_key_value("- a = b"));
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn test_from_path() {
+        assert_eq!(
+            ConfigFormat::from_path("/etc/app/config.YML"),
+            Some(ConfigFormat::Yaml)
+        );
+        assert_eq!(ConfigFormat::from_path("/etc/app/config"), None);
+        assert_eq!(ConfigFormat::Yaml.extensions(), ["yaml", "yml"]);
+        assert_eq!(ConfigFormat::Yaml.to_string(), "YAML");
+    }
+
+    #[test]
+    #[cfg(all(feature = "json", feature = "toml", feature = "xml", feature = "yaml"))]
+    fn test_detect() {
+        assert_eq!(
+            ConfigFormat::detect("<?xml version=\"1.0\"?>")[0],
+            ConfigFormat::Xml
+        );
+        assert_eq!(
+            ConfigFormat::detect("\u{feff}  { \"a\": 1 }")[0],
+            ConfigFormat::Json
+        );
+        assert_eq!(ConfigFormat::detect("[1, 2]")[0], ConfigFormat::Json);
+        assert_eq!(
+            ConfigFormat::detect("[server]\nhost = \"a\"")[0],
+            ConfigFormat::Toml
+        );
+        assert_eq!(ConfigFormat::detect("---\nhost: a")[0], ConfigFormat::Yaml);
+        assert_eq!(
+            ConfigFormat::detect("# comment\nhost = \"a\"")[0],
+            ConfigFormat::Toml
+        );
+        assert_eq!(ConfigFormat::detect("host: a")[0], ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::detect("host: a").len(), 4);
+    }
+}
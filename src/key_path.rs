@@ -0,0 +1,206 @@
+//! Key paths, such as `server.tags[1]`, locating the values of a configuration.
+
+use crate::value::Value;
+use std::collections::BTreeMap;
+
+/// Append @key_path to @prefix, array indices such as `[0]` being appended without a dot
+pub(crate) fn join(prefix: &str, key_path: &str) -> String {
+    if prefix.is_empty() || key_path.is_empty() || key_path.starts_with('[') {
+        format!("{}{}", prefix, key_path)
+    } else {
+        format!("{}.{}", prefix, key_path)
+    }
+}
+
+/// Whether @path is located inside @key_path
+pub(crate) fn is_within(path: &str, key_path: &str) -> bool {
+    path.strip_prefix(key_path)
+        .map_or(false, |rest| rest.starts_with(['.', '[']))
+}
+
+/// Replace @secrets with `<redacted>` in @message, whether they are quoted as is or escaped like
+/// serde does when quoting a string
+pub(crate) fn redact(message: &str, secrets: &[String]) -> String {
+    let mut message = message.to_string();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        let escaped = format!("{:?}", secret);
+        let escaped = &escaped[1..escaped.len() - 1];
+        message = message
+            .replace(escaped, "<redacted>")
+            .replace(secret.as_str(), "<redacted>");
+    }
+    message
+}
+
+/// Something attached to each value of a configuration, indexed by their key paths, such as
+/// whether they can be converted from strings
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct KeyPathMap<T>(BTreeMap<String, T>);
+
+impl<T> Default for KeyPathMap<T> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<T> KeyPathMap<T> {
+    /// Attach what @f returns to each of the values of @value, located at @key_path, replacing
+    /// what was attached to the values they override
+    pub(crate) fn record<F>(&mut self, key_path: String, value: &Value, f: &mut F)
+    where
+        F: FnMut(&str) -> T,
+    {
+        match value {
+            Value::Table(table) if !table.is_empty() => {
+                // Tables are merged, only a value they replace is overridden
+                self.0.remove(&key_path);
+                for (key, value) in table {
+                    self.record(join(&key_path, key), value, f);
+                }
+            }
+            _ if key_path.is_empty() => {}
+            _ => {
+                let attached = f(&key_path);
+                self.insert(key_path, attached);
+            }
+        }
+    }
+
+    /// Attach @attached to the value located at @key_path, replacing what was attached to the
+    /// values located inside it
+    pub(crate) fn insert(&mut self, key_path: String, attached: T) {
+        self.0.retain(|path, _| !is_within(path, &key_path));
+        self.0.insert(key_path, attached);
+    }
+
+    /// What is attached to the value located at @key_path, or to the array or table containing
+    /// it since they are set as a whole
+    pub(crate) fn get(&self, key_path: &str) -> Option<&T> {
+        let mut key_path = key_path;
+        loop {
+            if let Some(attached) = self.0.get(key_path) {
+                return Some(attached);
+            }
+            key_path = &key_path[..key_path.rfind(['.', '['])?];
+        }
+    }
+
+    /// The key path of each value along with what is attached to it, in alphabetical order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.0
+            .iter()
+            .map(|(key_path, attached)| (key_path.as_str(), attached))
+    }
+
+    /// What is attached to each of the values located inside @key_path
+    pub(crate) fn within<'a>(&'a self, key_path: &'a str) -> impl Iterator<Item = &'a T> + 'a {
+        self.0
+            .iter()
+            .filter(move |(path, _)| key_path.is_empty() || is_within(path, key_path))
+            .map(|(_, attached)| attached)
+    }
+}
+
+impl<T: Clone> KeyPathMap<T> {
+    /// Attach what is attached in @other on top of what we attach
+    pub(crate) fn merge(&mut self, other: KeyPathMap<T>) {
+        for (key_path, attached) in other.0 {
+            self.insert(key_path, attached);
+        }
+    }
+
+    /// Only keep what is attached to the values of the @common top-level keys, and move what is
+    /// attached to the values located in each of the top-level @tables, in turn, to the root
+    pub(crate) fn select(&mut self, common: &[&str], tables: &[&str]) {
+        let all = std::mem::take(&mut self.0);
+        for (key_path, attached) in &all {
+            let key = &key_path[..key_path.find(['.', '[']).unwrap_or(key_path.len())];
+            if common.contains(&key) {
+                self.insert(key_path.clone(), attached.clone());
+            }
+        }
+        for table in tables {
+            for (key_path, attached) in &all {
+                if let Some(key_path) = key_path
+                    .strip_prefix(table)
+                    .and_then(|rest| rest.strip_prefix('.'))
+                {
+                    self.insert(key_path.to_string(), attached.clone());
+                }
+            }
+        }
+    }
+}
+
+impl KeyPathMap<bool> {
+    /// Whether the value located at @key_path is flagged, or all the values inside it if it is a
+    /// table
+    pub(crate) fn is_flagged(&self, key_path: &str) -> bool {
+        match self.get(key_path) {
+            Some(flagged) => *flagged,
+            None => {
+                let mut within = self.within(key_path).peekable();
+                within.peek().is_some() && within.all(|flagged| *flagged)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join("", "host"), "host");
+        assert_eq!(join("server", "host"), "server.host");
+        assert_eq!(join("server", "[1]"), "server[1]");
+        assert_eq!(join("server", ""), "server");
+    }
+
+    #[test]
+    fn test_redact() {
+        let secrets = ["hunter2".to_string(), "line\n\"quoted\"".to_string()];
+        assert_eq!(
+            redact("string \"hunter2\", expected u16", &secrets),
+            "string \"<redacted>\", expected u16"
+        );
+        let message = format!("string {:?}, expected u16", secrets[1]);
+        assert_eq!(
+            redact(&message, &secrets),
+            "string \"<redacted>\", expected u16"
+        );
+        assert_eq!(redact("line\n\"quoted\"", &secrets), "<redacted>");
+        assert_eq!(redact("hunter", &[String::new()]), "hunter");
+    }
+
+    #[test]
+    fn test_key_path_map() {
+        let table = |entries: &[(&str, Value)]| {
+            Value::Table(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.clone()))
+                    .collect(),
+            )
+        };
+        let mut map = KeyPathMap::default();
+        let value = table(&[
+            ("port", Value::String("443".to_string())),
+            ("server", table(&[("host", Value::Null)])),
+            ("tags", Value::Array(vec![Value::Null])),
+        ]);
+        map.record(String::new(), &value, &mut |_| true);
+        map.record("server.port".to_string(), &Value::Integer(443), &mut |_| {
+            false
+        });
+        assert!(map.is_flagged("port"));
+        assert!(map.is_flagged("tags[0]"));
+        assert!(map.is_flagged("server.host"));
+        assert!(!map.is_flagged("server.port"));
+        assert!(!map.is_flagged("server"));
+        assert!(!map.is_flagged("other"));
+        map.record("tags".to_string(), &Value::Null, &mut |_| false);
+        assert!(!map.is_flagged("tags[0]"));
+    }
+}
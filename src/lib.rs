@@ -1,10 +1,11 @@
 #![deny(missing_docs)]
 #![warn(rust_2018_idioms)]
-#![doc(html_root_url = "https://docs.rs/config-file/0.2.3/")]
+#![doc(html_root_url = "https://docs.rs/config-file/0.3.0/")]
 
 //! # Read and parse configuration file automatically
 //!
 //! config-file reads your configuration files and parse them automatically using their extension.
+//! It can also write them back, picking the format the same way.
 //!
 //! # Features
 //!
@@ -12,6 +13,8 @@
 //! - json is optional
 //! - xml is optional
 //! - yaml is optional
+//! - async is optional, providing `from_config_file_async` using tokio
+//! - schema is optional, providing JSON Schema generation and validation using schemars
 //!
 //! # Examples
 //!
@@ -26,9 +29,84 @@
 //!
 //! let config = Config::from_config_file("/etc/myconfig.toml").unwrap();
 //! ```
+//!
+//! When the format is known out of band, it can be given explicitly:
+//!
+//! ```rust,no_run
+//! use config_file::{ConfigFormat, FromConfigFile};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Config {
+//!     host: String,
+//! }
+//!
+//! # #[cfg(feature = "toml")]
+//! # fn main() {
+//! let config = Config::from_config_reader(std::io::stdin(), ConfigFormat::Toml).unwrap();
+//! # }
+//! # #[cfg(not(feature = "toml"))]
+//! # fn main() {}
+//! ```
+//!
+//! ```rust,no_run
+//! use config_file::ToConfigFile;
+//! use serde::Serialize;
+//!
+//! #[derive(Serialize)]
+//! struct Config {
+//!     host: String,
+//! }
+//!
+//! let config = Config { host: "example.com".to_string() };
+//! config.to_config_file("/etc/myconfig.toml").unwrap();
+//! ```
+//!
+//! Configurations can also be inspected without a concrete type, through [`ConfigValue`]:
+//!
+//! ```rust,no_run
+//! use config_file::{ConfigValue, FromConfigFile};
+//!
+//! let config = ConfigValue::from_config_file("/etc/myconfig.toml").unwrap();
+//! println!("{:?}", config.get("host"));
+//! ```
+
+#[cfg(feature = "async")]
+mod asynchronous;
+mod atomic;
+mod discovery;
+mod error;
+mod format;
+mod key_path;
+mod loader;
+mod provenance;
+mod registry;
+#[cfg(feature = "schema")]
+mod schema;
+mod secrets;
+mod validate;
+mod value;
+mod watch;
+
+#[cfg(feature = "async")]
+pub use asynchronous::from_config_file_async;
+pub use discovery::{AmbiguityPolicy, ConfigDiscovery};
+pub use error::ParseError;
+pub use format::ConfigFormat;
+pub use loader::{ConfigLoader, Loaded, UnknownKeys};
+pub use provenance::{Origin, Provenance};
+pub use registry::FormatRegistry;
+#[cfg(feature = "schema")]
+pub use schema::json_schema;
+pub use validate::{Validate, Violation, Violations};
+pub use value::{Value as ConfigValue, ValueError};
+pub use watch::{ConfigWatcher, WatchHandle};
 
-use serde::de::DeserializeOwned;
-use std::{ffi::OsStr, fs::File, path::Path};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 #[cfg(feature = "toml")]
 use toml_crate as toml;
@@ -40,6 +118,87 @@ pub trait FromConfigFile {
     fn from_config_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
     where
         Self: Sized;
+
+    /// Load ourselves from the configuration file located at @path, guessing its format from its
+    /// contents if its extension is missing or unknown.
+    ///
+    /// The enabled formats are tried in turn, starting with the most likely ones, until one of
+    /// them succeeds.
+    fn from_config_file_detect<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized;
+
+    /// Load ourselves from the configuration file located at @path using @format, whatever its
+    /// extension
+    fn from_config_file_with_format<P: AsRef<Path>>(
+        path: P,
+        format: ConfigFormat,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized;
+
+    /// Load ourselves from the configuration file located at @path like
+    /// [`FromConfigFile::from_config_file`], and then check that we are valid, reporting all the
+    /// violations as [`ConfigFileError::Validation`]
+    fn from_config_file_validated<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized + Validate;
+
+    /// Load ourselves from the configuration file located at @path like
+    /// [`FromConfigFile::from_config_file`], merged on top of our default values so that it only
+    /// needs to contain the ones it changes, see [`ConfigLoader::defaults`]
+    fn from_config_file_with_defaults<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized + Default + Serialize;
+
+    /// Load ourselves from the configuration file located at @path like
+    /// [`FromConfigFile::from_config_file`], and then apply @overrides, such as
+    /// `["database.pool_size=20"]` collected from `--set` command-line arguments, see
+    /// [`ConfigLoader::overrides`]
+    fn from_config_file_with_overrides<P, I, S>(
+        path: P,
+        overrides: I,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+        P: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>;
+
+    /// Load ourselves from the @profile section of the configuration file located at @path,
+    /// merged on top of its `default` section, @profiles being the names of all the sections
+    /// which are profiles, see [`ConfigLoader::profile`]
+    fn from_config_file_with_profile<P, I, S>(
+        path: P,
+        profiles: I,
+        profile: &str,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+        P: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>;
+
+    /// Load ourselves from the files of the drop-in directory located at @path, such as
+    /// `/etc/app/conf.d`, merged in lexical order.
+    ///
+    /// Use [`ConfigLoader::dir`] to merge them on top of a main file.
+    fn from_config_dir<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized;
+
+    /// Load ourselves from @contents using @format
+    fn from_config_str(contents: &str, format: ConfigFormat) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized;
+
+    /// Load ourselves from the data read from @reader using @format
+    fn from_config_reader<R: Read>(
+        reader: R,
+        format: ConfigFormat,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized;
 }
 
 impl<C: DeserializeOwned> FromConfigFile for C {
@@ -48,45 +207,156 @@ impl<C: DeserializeOwned> FromConfigFile for C {
         Self: Sized,
     {
         let path = path.as_ref();
-        let extension = path
-            .extension()
-            .and_then(OsStr::to_str)
-            .map(|extension| extension.to_lowercase());
-        match extension.as_deref() {
-            #[cfg(feature = "json")]
-            Some("json") => {
-                serde_json::from_reader(open_file(path)?).map_err(ConfigFileError::Json)
-            }
-            #[cfg(feature = "toml")]
-            Some("toml") => toml::from_str(
-                std::fs::read_to_string(path)
-                    .map_err(ConfigFileError::FileAccess)?
-                    .as_str(),
-            )
-            .map_err(ConfigFileError::Toml),
-            #[cfg(feature = "xml")]
-            Some("xml") => {
-                serde_xml_rs::from_reader(open_file(path)?).map_err(ConfigFileError::Xml)
-            }
-            #[cfg(feature = "yaml")]
-            Some("yaml") | Some("yml") => {
-                serde_yaml::from_reader(open_file(path)?).map_err(ConfigFileError::Yaml)
+        let format = ConfigFormat::from_path(path).ok_or(ConfigFileError::UnsupportedFormat)?;
+        Self::from_config_file_with_format(path, format)
+    }
+
+    fn from_config_file_detect<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+    {
+        let path = path.as_ref();
+        if let Some(format) = ConfigFormat::from_path(path) {
+            return Self::from_config_file_with_format(path, format);
+        }
+        let contents = read_file(path)?;
+        let mut errors = Vec::new();
+        for format in ConfigFormat::detect(&contents) {
+            match format.deserialize(&contents) {
+                Ok(config) => return Ok(config),
+                Err(err) => errors.push(err.with_path(path)),
             }
-            _ => Err(ConfigFileError::UnsupportedFormat),
+        }
+        if errors.is_empty() {
+            Err(ConfigFileError::UnsupportedFormat)
+        } else {
+            Err(ConfigFileError::FormatDetection(errors))
         }
     }
+
+    fn from_config_file_with_format<P: AsRef<Path>>(
+        path: P,
+        format: ConfigFormat,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+    {
+        let path = path.as_ref();
+        format
+            .deserialize(&read_file(path)?)
+            .map_err(|err| err.with_path(path))
+    }
+
+    fn from_config_file_validated<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized + Validate,
+    {
+        let config = Self::from_config_file(path)?;
+        Violations::check(&config).map_err(ConfigFileError::Validation)?;
+        Ok(config)
+    }
+
+    fn from_config_file_with_defaults<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized + Default + Serialize,
+    {
+        ConfigLoader::new()
+            .defaults(&Self::default())
+            .file(path.as_ref())
+            .load()
+    }
+
+    fn from_config_file_with_overrides<P, I, S>(
+        path: P,
+        overrides: I,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+        P: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ConfigLoader::new()
+            .file(path.as_ref())
+            .overrides(overrides)
+            .load()
+    }
+
+    fn from_config_file_with_profile<P, I, S>(
+        path: P,
+        profiles: I,
+        profile: &str,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+        P: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ConfigLoader::new()
+            .file(path.as_ref())
+            .profiles(profiles)
+            .profile(profile)
+            .load()
+    }
+
+    fn from_config_dir<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+    {
+        ConfigLoader::new().dir(path.as_ref()).load()
+    }
+
+    fn from_config_str(contents: &str, format: ConfigFormat) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+    {
+        format.deserialize(contents)
+    }
+
+    fn from_config_reader<R: Read>(
+        mut reader: R,
+        format: ConfigFormat,
+    ) -> Result<Self, ConfigFileError>
+    where
+        Self: Sized,
+    {
+        let mut contents = String::new();
+        reader
+            .read_to_string(&mut contents)
+            .map_err(ConfigFileError::FileAccess)?;
+        format.deserialize(&contents)
+    }
 }
 
-#[allow(unused)]
-fn open_file(path: &Path) -> Result<File, ConfigFileError> {
-    File::open(path).map_err(ConfigFileError::FileAccess)
+/// Trait for saving a struct to a configuration file.
+/// This trait is automatically implemented when serde::Serialize is.
+///
+/// The file is replaced atomically: the data is first written to a temporary file in the same
+/// directory, flushed to disk and then renamed over the target, keeping its permissions and, when
+/// allowed to, its ownership.
+pub trait ToConfigFile {
+    /// Save ourselves to the configuration file located at @path
+    fn to_config_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigFileError>;
+}
+
+impl<C: Serialize> ToConfigFile for C {
+    fn to_config_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigFileError> {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path).ok_or(ConfigFileError::UnsupportedFormat)?;
+        atomic::write(path, &format.serialize(self)?).map_err(ConfigFileError::FileAccess)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ConfigFileError> {
+    std::fs::read_to_string(path).map_err(ConfigFileError::FileAccess)
 }
 
 /// This type represents all possible errors that can occur when loading data from a configuration file.
 #[derive(Error, Debug)]
 pub enum ConfigFileError {
     #[error("couldn't read config file")]
-    /// There was an error while reading the configuration file
+    /// There was an error while reading or writing the configuration file
     FileAccess(#[from] std::io::Error),
     #[cfg(feature = "json")]
     #[error("couldn't parse JSON file")]
@@ -104,18 +374,177 @@ pub enum ConfigFileError {
     #[error("couldn't parse YAML file")]
     /// There was an error while parsing the YAML data
     Yaml(#[from] serde_yaml::Error),
+    #[cfg(feature = "json")]
+    #[error("couldn't serialize JSON file")]
+    /// There was an error while serializing the JSON data
+    JsonSerialize(serde_json::Error),
+    #[cfg(feature = "toml")]
+    #[error("couldn't serialize TOML file")]
+    /// There was an error while serializing the TOML data
+    TomlSerialize(toml::ser::Error),
+    #[cfg(feature = "xml")]
+    #[error("couldn't serialize XML file")]
+    /// There was an error while serializing the XML data
+    XmlSerialize(serde_xml_rs::Error),
+    #[cfg(feature = "yaml")]
+    #[error("couldn't serialize YAML file")]
+    /// There was an error while serializing the YAML data
+    YamlSerialize(serde_yaml::Error),
+    #[error("{0}")]
+    /// There was an error while parsing a configuration file, see [`ParseError`] for the details
+    Parse(Box<ParseError>),
+    #[error("couldn't parse file with custom format")]
+    /// There was an error while parsing a file with a custom format from a [`FormatRegistry`]
+    Custom(Box<dyn std::error::Error + Send + Sync>),
+    #[error("couldn't deserialize configuration")]
+    /// There was an error while deserializing the merged configuration
+    Value(#[from] ValueError),
+    #[error("couldn't detect file format: {}", display_attempts(.0))]
+    /// None of the enabled formats could parse the file, here is what each of them reported
+    FormatDetection(Vec<ConfigFileError>),
+    #[error("invalid configuration: {}", display_violations(.0))]
+    /// The configuration doesn't satisfy its [`Validate`] constraints or its JSON Schema
+    Validation(Vec<Violation>),
+    #[error("unknown keys in config: {}", .0.join(", "))]
+    /// Some keys of the configuration files aren't used by the configuration type, see
+    /// [`UnknownKeys::Deny`]
+    UnknownKeys(Vec<String>),
+    #[error("couldn't find config file in {}", display_paths(.0))]
+    /// No configuration file was found in any of these directories
+    NotFound(Vec<PathBuf>),
+    #[error("found several config files for the same stem: {}", display_paths(.0))]
+    /// Several files with the same stem but different formats exist in the same directory
+    Ambiguous(Vec<PathBuf>),
+    #[error("couldn't interpolate {key_path}: {reason}")]
+    /// A `${...}` reference couldn't be resolved
+    Interpolation {
+        /// The path of the key whose value contains the reference
+        key_path: String,
+        /// Why it couldn't be resolved
+        reason: String,
+    },
+    #[error("invalid override {arg:?}: {reason}")]
+    /// A command-line override couldn't be applied, see [`ConfigLoader::overrides`]
+    Override {
+        /// The override, as given
+        arg: String,
+        /// Why it couldn't be applied
+        reason: String,
+    },
+    #[error("unknown profile {profile}, available profiles: {}", .available.join(", "))]
+    /// The selected profile isn't one of the declared ones, see [`ConfigLoader::profile`]
+    UnknownProfile {
+        /// The selected profile
+        profile: String,
+        /// The declared profiles, `default` included
+        available: Vec<String>,
+    },
+    #[error("couldn't read secret {} for {key_path}", .path.display())]
+    /// A secret referenced with a `_file` key or a `file:` value couldn't be read
+    Secret {
+        /// The path of the key referencing the secret
+        key_path: String,
+        /// The path of the secret
+        path: PathBuf,
+        /// Why it couldn't be read
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("couldn't find config file {} included from {}", .path.display(), .from.display())]
+    /// A file listed in the includes of another one doesn't exist
+    IncludeNotFound {
+        /// The missing file
+        path: PathBuf,
+        /// The file which includes it
+        from: PathBuf,
+    },
+    #[error("config files include themselves: {}", display_chain(.0))]
+    /// A file includes itself, directly or through other files, here is the include chain
+    RecursiveInclude(Vec<PathBuf>),
+    #[error("couldn't load config fragment {}", .path.display())]
+    /// There was an error while loading a file from a drop-in directory
+    Fragment {
+        /// The file which failed to load
+        path: PathBuf,
+        /// What went wrong
+        #[source]
+        source: Box<ConfigFileError>,
+    },
     #[error("don't know how to parse file")]
     /// We don't know how to parse this format according to the file extension
     UnsupportedFormat,
 }
 
+impl ConfigFileError {
+    /// The path of the key whose value failed to deserialize, such as `inner.answer` or
+    /// `tags[1]`, when known
+    pub fn key_path(&self) -> Option<&str> {
+        match self {
+            Self::Parse(err) => err.key_path(),
+            Self::Value(err) => err.key_path(),
+            Self::Fragment { source, .. } => source.key_path(),
+            Self::Interpolation { key_path, .. } | Self::Secret { key_path, .. } => Some(key_path),
+            _ => None,
+        }
+    }
+
+    /// Attach @path to parse errors, so that we know which file failed
+    pub(crate) fn with_path(mut self, path: &Path) -> Self {
+        if let Self::Parse(err) = &mut self {
+            err.set_path(path);
+        }
+        self
+    }
+}
+
+impl From<ParseError> for ConfigFileError {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(Box::new(err))
+    }
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn display_violations(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn display_chain(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+fn display_attempts(errors: &[ConfigFileError]) -> String {
+    errors
+        .iter()
+        .map(|err| match std::error::Error::source(err) {
+            Some(source) => format!("{} ({})", err, source),
+            None => err.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
 
-    #[derive(Debug, Deserialize, PartialEq)]
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
     struct TestConfig {
         host: String,
         port: u64,
@@ -123,7 +552,7 @@ mod test {
         inner: TestConfigInner,
     }
 
-    #[derive(Debug, Deserialize, PartialEq)]
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
     struct TestConfigInner {
         answer: u8,
     }
@@ -180,4 +609,263 @@ mod test {
         let config = TestConfig::from_config_file("testdata/config.yml");
         assert_eq!(config.unwrap(), TestConfig::example());
     }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_detect_extension() {
+        let config = TestConfig::from_config_file_detect("testdata/config.toml");
+        assert_eq!(config.unwrap(), TestConfig::example());
+    }
+
+    #[test]
+    #[cfg(all(feature = "json", feature = "toml", feature = "xml", feature = "yaml"))]
+    fn test_detect_content() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["config.json", "config.toml", "config.xml", "config.yml"] {
+            let path = dir.path().join("config");
+            std::fs::copy(format!("testdata/{}", file), &path).unwrap();
+            let config = TestConfig::from_config_file_detect(&path);
+            assert_eq!(config.unwrap(), TestConfig::example());
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "json", feature = "toml"))]
+    fn test_detect_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".apprc");
+        std::fs::write(&path, "host = \"example.com\"\n").unwrap();
+        let config = TestConfig::from_config_file_detect(&path);
+        match config {
+            Err(ConfigFileError::FormatDetection(errors)) => {
+                let inner = |err: &ConfigFileError| match err {
+                    ConfigFileError::Parse(err) => format!("{:?}", err.error()),
+                    _ => String::new(),
+                };
+                assert!(errors.iter().any(|err| inner(err).starts_with("Toml")));
+                assert!(errors.iter().any(|err| inner(err).starts_with("Json")));
+            }
+            _ => panic!("unexpected result: {:?}", config),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_with_format() {
+        let config =
+            TestConfig::from_config_file_with_format("testdata/config.json", ConfigFormat::Json);
+        assert_eq!(config.unwrap(), TestConfig::example());
+    }
+
+    impl Validate for TestConfig {
+        fn validate(&self, violations: &mut Violations) {
+            if self.tags.iter().any(|tag| tag == "test") {
+                violations.add("tags", "test configurations are forbidden");
+            }
+            if self.inner.answer == 42 {
+                violations.add("inner.answer", "don't panic");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_validated() {
+        let config = TestConfig::from_config_file_validated("testdata/config.toml");
+        match config {
+            Err(ConfigFileError::Validation(violations)) => {
+                assert_eq!(violations.len(), 2);
+                assert_eq!(violations[0].key_path(), Some("tags"));
+                assert_eq!(violations[1].message(), "don't panic");
+            }
+            _ => panic!("unexpected result: {:?}", config),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_str() {
+        let config = TestConfig::from_config_str(
+            &std::fs::read_to_string("testdata/config.toml").unwrap(),
+            ConfigFormat::Toml,
+        );
+        assert_eq!(config.unwrap(), TestConfig::example());
+        let config = TestConfig::from_config_str("port = \"443\"", ConfigFormat::Toml);
+        match config {
+            Err(ConfigFileError::Parse(err)) => {
+                assert!(matches!(err.error(), ConfigFileError::Toml(_)));
+                assert_eq!(err.path(), None);
+            }
+            _ => panic!("unexpected result: {:?}", config),
+        }
+    }
+
+    #[allow(unused)]
+    fn parse_error(file_name: &str, contents: &str) -> ParseError {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(file_name);
+        std::fs::write(&path, contents).unwrap();
+        match TestConfig::from_config_file(&path) {
+            Err(ConfigFileError::Parse(err)) => {
+                assert_eq!(err.path(), Some(path.as_path()));
+                *err
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_json_location() {
+        let err = parse_error(
+            "config.json",
+            "{\n  \"host\": \"example.com\",\n  \"port\": true\n}",
+        );
+        assert_eq!((err.line(), err.column()), (Some(3), Some(14)));
+        assert_eq!(
+            err.snippet(),
+            Some("  |\n3 |   \"port\": true\n  |              ^")
+        );
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(source.is::<serde_json::Error>());
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_toml_location() {
+        let err = parse_error("config.toml", "host = \"example.com\"\nport = \"443\"\n");
+        assert_eq!((err.line(), err.column()), (Some(2), Some(8)));
+        let message = err.to_string();
+        assert!(message.starts_with("couldn't parse TOML file "));
+        assert!(message.contains("config.toml at line 2, column 8: port: invalid type"));
+        assert!(message.ends_with("\n  |\n2 | port = \"443\"\n  |        ^"));
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_json_key_path() {
+        let err = parse_error(
+            "config.json",
+            r#"{"host": "a", "port": 1, "tags": ["a", 2], "inner": {"answer": 42}}"#,
+        );
+        assert_eq!(err.key_path(), Some("tags[1]"));
+        let err = parse_error("config.json", "{\"inner\": {\"answer\": \"42\"}}");
+        assert_eq!(err.key_path(), Some("inner.answer"));
+        assert!(err
+            .to_string()
+            .contains(": inner.answer: invalid type: string \"42\", expected u8"));
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_toml_key_path() {
+        let err = parse_error(
+            "config.toml",
+            "host = \"a\"\nport = 1\ntags = []\n[inner]\nanswer = 420\n",
+        );
+        assert_eq!(err.key_path(), Some("inner.answer"));
+        let err = ConfigFileError::Parse(Box::new(err));
+        assert_eq!(err.key_path(), Some("inner.answer"));
+        let err = parse_error("config.toml", "host = \n");
+        assert_eq!(err.key_path(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn test_xml_key_path() {
+        let err = parse_error(
+            "config.xml",
+            "<c><host>a</host><port>1</port><tags>a</tags><inner><answer>x</answer></inner></c>",
+        );
+        assert_eq!(err.key_path(), Some("inner.answer"));
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn test_yaml_key_path() {
+        let err = parse_error(
+            "config.yml",
+            "host: a\nport: 1\ntags: []\ninner:\n  answer: [42]\n",
+        );
+        assert_eq!(err.key_path(), Some("inner.answer"));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn test_xml_location() {
+        let err = parse_error(
+            "config.xml",
+            "<config>\n  <host>example.com</port>\n</config>",
+        );
+        assert_eq!(err.line(), Some(2));
+        assert!(err.snippet().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn test_yaml_location() {
+        let err = parse_error("config.yml", "host: example.com\nport: [443\n");
+        assert_eq!(err.line(), Some(3));
+        let err = parse_error("config.yml", "host: example.com\n  port: 443\n");
+        assert_eq!(err.line(), Some(2));
+        assert!(err.snippet().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn test_reader() {
+        let config = TestConfig::from_config_reader(
+            std::fs::File::open("testdata/config.yml").unwrap(),
+            ConfigFormat::Yaml,
+        );
+        assert_eq!(config.unwrap(), TestConfig::example());
+    }
+
+    #[test]
+    fn test_write_unknown() {
+        let dir = tempfile::tempdir().unwrap();
+        let res = TestConfig::example().to_config_file(dir.path().join("foobar"));
+        assert!(matches!(res, Err(ConfigFileError::UnsupportedFormat)));
+    }
+
+    #[allow(unused)]
+    fn roundtrip(file_name: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(file_name);
+        TestConfig::example().to_config_file(&path).unwrap();
+        let config = TestConfig::from_config_file(&path);
+        assert_eq!(config.unwrap(), TestConfig::example());
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_write_json() {
+        roundtrip("config.json");
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_write_toml() {
+        roundtrip("config.toml");
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn test_write_xml() {
+        // serde-xml-rs doesn't know how to serialize sequences
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.xml");
+        let res = TestConfig::example().to_config_file(&path);
+        assert!(matches!(res, Err(ConfigFileError::XmlSerialize(_))));
+        let inner = TestConfigInner { answer: 42 };
+        inner.to_config_file(&path).unwrap();
+        let config = TestConfigInner::from_config_file(&path);
+        assert_eq!(config.unwrap(), inner);
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn test_write_yaml() {
+        roundtrip("config.YAML");
+    }
 }
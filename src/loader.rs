@@ -0,0 +1,1959 @@
+use crate::{
+    key_path::KeyPathMap,
+    provenance::{find_line, Origin, Provenance},
+    secrets,
+    value::{self, Value, ValueError},
+    ConfigFileError, ConfigFormat, FormatRegistry, Validate, Violation, Violations,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The profile which the other ones are merged on top of, see [`ConfigLoader::profile`]
+const DEFAULT_PROFILE: &str = "default";
+
+/// Load a configuration out of several layered files.
+///
+/// Files are read in the order they were added, each one overriding the previous ones key by key,
+/// on top of the defaults, see [`ConfigLoader::defaults`].
+/// Tables are merged recursively, so that a file only needs to contain the values it changes.
+/// Files can be of different formats, a YAML user file can override a TOML system file. Values
+/// coming from XML files, which only has strings, are converted to the types of the
+/// configuration; values of the other formats must already have the right type.
+///
+/// Drop-in directories add their files on top of the previous ones, see [`ConfigLoader::dir`].
+/// Files can include other ones, see [`ConfigLoader::includes`], and contain several profiles,
+/// see [`ConfigLoader::profile`].
+///
+/// Environment variables can then override individual keys, see [`ConfigLoader::env_prefix`],
+/// followed by command-line arguments, see [`ConfigLoader::overrides`]. Strings can refer to
+/// other values, see [`ConfigLoader::interpolate`], and secrets can be read from separate files,
+/// see [`ConfigLoader::secret_keys`].
+///
+/// ```rust,no_run
+/// use config_file::ConfigLoader;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     host: String,
+/// }
+///
+/// let config: Config = ConfigLoader::new()
+///     .file("/etc/app/config.toml")
+///     .optional_file("/home/user/.config/app/config.yaml")
+///     .optional_file("app.toml")
+///     .load()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConfigLoader {
+    formats: FormatRegistry,
+    defaults: Vec<Defaults>,
+    files: Vec<Layer>,
+    include_key: Option<String>,
+    profiles: Vec<String>,
+    profile: Option<Profile>,
+    env: Option<EnvOverrides>,
+    vars: Option<Vec<(String, String)>>,
+    overrides: Vec<String>,
+    interpolate: bool,
+    secret_keys: Vec<String>,
+    unknown_keys: UnknownKeys,
+    track_origins: bool,
+}
+
+/// What to do with the keys which are present in the files but not used by the configuration
+/// type, which usually are typos
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownKeys {
+    /// Silently ignore them, like serde does
+    Ignore,
+    /// Report them through [`Loaded::unknown_keys`], see [`ConfigLoader::load_detailed`]
+    Warn,
+    /// Fail with [`ConfigFileError::UnknownKeys`]
+    Deny,
+}
+
+/// A configuration loaded by [`ConfigLoader::load_detailed`], along with what was noticed while
+/// loading it
+#[derive(Clone, Debug)]
+pub struct Loaded<C> {
+    config: C,
+    unknown_keys: Vec<String>,
+    provenance: Option<Provenance>,
+}
+
+impl<C> Loaded<C> {
+    /// The loaded configuration
+    pub fn config(&self) -> &C {
+        &self.config
+    }
+
+    /// Consume ourselves, returning the loaded configuration
+    pub fn into_config(self) -> C {
+        self.config
+    }
+
+    /// The paths of the keys which are present in the files but not used by the configuration
+    /// type, such as `server.prot`, when [`UnknownKeys::Warn`] is used
+    pub fn unknown_keys(&self) -> &[String] {
+        &self.unknown_keys
+    }
+
+    /// Where each value comes from, when [`ConfigLoader::track_origins`] is used
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+}
+
+impl Default for UnknownKeys {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Defaults {
+    Value(Result<Value, ValueError>),
+    Embedded {
+        contents: String,
+        format: ConfigFormat,
+    },
+}
+
+impl Defaults {
+    fn load(&self) -> Result<Value, ConfigFileError> {
+        match self {
+            Defaults::Value(value) => Ok(value.clone()?),
+            Defaults::Embedded { contents, format } => format.deserialize(contents),
+        }
+    }
+
+    /// Whether our values come from a format which only has strings
+    fn is_untyped(&self) -> bool {
+        match self {
+            Defaults::Value(_) => false,
+            Defaults::Embedded { format, .. } => format.is_untyped(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Layer {
+    path: PathBuf,
+    optional: bool,
+    dir: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Profile {
+    Name(String),
+    Env(String),
+}
+
+#[derive(Clone, Debug)]
+struct EnvOverrides {
+    prefix: String,
+    separator: String,
+}
+
+impl EnvOverrides {
+    /// Set the overrides in @merged, flagging them in @untyped since they are plain strings
+    fn apply(
+        &self,
+        merged: &mut Value,
+        vars: Vec<(String, String)>,
+        untyped: &mut KeyPathMap<bool>,
+        provenance: &mut Option<Provenance>,
+    ) {
+        let prefix = format!("{}_", self.prefix);
+        for (name, value) in vars {
+            let key = match name.strip_prefix(&prefix) {
+                Some(key) if !key.is_empty() => key,
+                _ => continue,
+            };
+            let path = key
+                .split(self.separator.as_str())
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>();
+            let value = Value::parse_override(&value);
+            untyped.record(path.join("."), &value, &mut |_| true);
+            if let Some(provenance) = provenance {
+                provenance.record(path.join("."), &value, &mut |_| Origin::Env {
+                    name: name.clone(),
+                });
+            }
+            merged.set_path(&path, value);
+        }
+    }
+}
+
+impl ConfigLoader {
+    /// Create a loader without any file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the files using the formats from @registry instead of the built-in ones
+    pub fn formats(mut self, registry: FormatRegistry) -> Self {
+        self.formats = registry;
+        self
+    }
+
+    /// Use @defaults, such as `Config::default()`, as the base layer under all the files, so
+    /// that they only need to contain the values they change.
+    ///
+    /// ```rust,no_run
+    /// use config_file::ConfigLoader;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Default, Deserialize, Serialize)]
+    /// struct Config {
+    ///     host: String,
+    ///     port: u16,
+    /// }
+    ///
+    /// let config: Config = ConfigLoader::new()
+    ///     .defaults(&Config::default())
+    ///     .file("/etc/app/config.toml")
+    ///     .load()
+    ///     .unwrap();
+    /// ```
+    pub fn defaults<C: Serialize>(mut self, defaults: &C) -> Self {
+        self.defaults
+            .push(Defaults::Value(value::to_value(defaults)));
+        self
+    }
+
+    /// Use @contents, written using @format, as the base layer under all the files like
+    /// [`ConfigLoader::defaults`], typically a default configuration file embedded with
+    /// `include_str!`.
+    ///
+    /// ```rust,no_run
+    /// use config_file::{ConfigFormat, ConfigLoader};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     host: String,
+    ///     port: u16,
+    /// }
+    ///
+    /// // Usually include_str!("default.toml")
+    /// const DEFAULTS: &str = "host = \"localhost\"\nport = 8080\n";
+    ///
+    /// # #[cfg(feature = "toml")]
+    /// # fn main() {
+    /// let config: Config = ConfigLoader::new()
+    ///     .defaults_str(DEFAULTS, ConfigFormat::Toml)
+    ///     .file("/etc/app/config.toml")
+    ///     .load()
+    ///     .unwrap();
+    /// # }
+    /// # #[cfg(not(feature = "toml"))]
+    /// # fn main() {}
+    /// ```
+    pub fn defaults_str<S: Into<String>>(mut self, contents: S, format: ConfigFormat) -> Self {
+        self.defaults.push(Defaults::Embedded {
+            contents: contents.into(),
+            format,
+        });
+        self
+    }
+
+    /// Add a file which must exist on top of the previous ones
+    pub fn file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files.push(Layer {
+            path: path.into(),
+            optional: false,
+            dir: false,
+        });
+        self
+    }
+
+    /// Add a file on top of the previous ones, skipping it if it doesn't exist
+    pub fn optional_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files.push(Layer {
+            path: path.into(),
+            optional: true,
+            dir: false,
+        });
+        self
+    }
+
+    /// Add the files of the drop-in directory located at @path, such as `/etc/app/conf.d`, on
+    /// top of the previous ones.
+    ///
+    /// The files with a supported extension are merged in lexical order, so that they can be
+    /// prefixed with a number to control it. Hidden files and backups such as `config.toml~` or
+    /// `config.toml.dpkg-old` are skipped. Errors are reported as
+    /// [`ConfigFileError::Fragment`], along with the file which caused them.
+    pub fn dir<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files.push(Layer {
+            path: path.into(),
+            optional: false,
+            dir: true,
+        });
+        self
+    }
+
+    /// Add the files of a drop-in directory like [`ConfigLoader::dir`], skipping it if it doesn't
+    /// exist
+    pub fn optional_dir<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files.push(Layer {
+            path: path.into(),
+            optional: true,
+            dir: true,
+        });
+        self
+    }
+
+    /// Let files include other ones by listing them under @key.
+    ///
+    /// The value of @key is a path or a list of paths, relative to the including file, which can
+    /// contain glob patterns such as `conf.d/*.toml`. The included files are merged in the order
+    /// they are listed, the files matching a pattern in lexical order, and the including file
+    /// is merged on top of them. Included files can include other ones.
+    ///
+    /// ```toml
+    /// include = ["db.toml", "cache.yaml"]
+    /// ```
+    pub fn includes<S: Into<String>>(mut self, key: S) -> Self {
+        self.include_key = Some(key.into());
+        self
+    }
+
+    /// Declare the top-level tables of the files, besides `default`, which are profiles, see
+    /// [`ConfigLoader::profile`]
+    pub fn profiles<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.profiles.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only use the `default` section of the files along with the @name one merged on top of it,
+    /// ignoring the other profiles.
+    ///
+    /// The profiles are the `default` table and the ones declared with
+    /// [`ConfigLoader::profiles`], failing with [`ConfigFileError::UnknownProfile`] if @name
+    /// isn't one of them. The other top-level keys, tables included, are shared by all the
+    /// profiles, which can override them. The defaults set with [`ConfigLoader::defaults`]
+    /// aren't split into profiles.
+    ///
+    /// ```toml
+    /// name = "app"
+    ///
+    /// [logging]
+    /// level = "info"
+    ///
+    /// [default]
+    /// host = "localhost"
+    /// port = 8080
+    ///
+    /// [production]
+    /// host = "example.com"
+    /// ```
+    ///
+    /// ```rust,no_run
+    /// use config_file::ConfigLoader;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     name: String,
+    ///     host: String,
+    ///     port: u16,
+    /// }
+    ///
+    /// let config: Config = ConfigLoader::new()
+    ///     .file("/etc/app/config.toml")
+    ///     .profiles(["staging", "production"])
+    ///     .profile("production")
+    ///     .load()
+    ///     .unwrap();
+    /// ```
+    pub fn profile<S: Into<String>>(mut self, name: S) -> Self {
+        self.profile = Some(Profile::Name(name.into()));
+        self
+    }
+
+    /// Select the profile like [`ConfigLoader::profile`] with the value of the environment
+    /// variable @var, such as `APP_PROFILE`, only using the `default` section if it isn't set.
+    /// The variable doesn't override any key, even if it starts with the prefix given to
+    /// [`ConfigLoader::env_prefix`].
+    pub fn profile_env<S: Into<String>>(mut self, var: S) -> Self {
+        self.profile = Some(Profile::Env(var.into()));
+        self
+    }
+
+    /// Override values with the environment variables starting with @prefix followed by an
+    /// underscore.
+    ///
+    /// The rest of the variable name is lowercased and split on the separator ("__" unless
+    /// changed with [`ConfigLoader::env_separator`]) to find the key to override, so that
+    /// `APP_DATABASE__HOST` overrides `database.host` with the prefix "APP".
+    /// Values are converted to booleans or numbers when the configuration type expects one, so
+    /// that `APP_PASSWORD=007` stays a string, and to lists when they are surrounded with
+    /// brackets, like `[1, 2, 3]`.
+    pub fn env_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.env_mut().prefix = prefix.into();
+        self
+    }
+
+    /// Use @separator to split nested keys in environment variable names
+    pub fn env_separator<S: Into<String>>(mut self, separator: S) -> Self {
+        self.env_mut().separator = separator.into();
+        self
+    }
+
+    /// Read the overrides and the interpolated variables from @vars instead of the process
+    /// environment
+    pub fn env_vars<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.vars = Some(
+            vars.into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Override values with @overrides of the form `key.path=value`, such as the arguments of
+    /// `--set` command-line options, applied in order on top of everything else.
+    ///
+    /// Values are parsed like environment variables, see [`ConfigLoader::env_prefix`], and key
+    /// paths can refer to array elements, like `servers[1].host`. An element can be appended by
+    /// using the length of the array as index.
+    ///
+    /// ```rust,no_run
+    /// use config_file::ConfigLoader;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     pool_size: u32,
+    /// }
+    ///
+    /// let config: Config = ConfigLoader::new()
+    ///     .file("/etc/app/config.toml")
+    ///     .overrides(["pool_size=20"])
+    ///     .load()
+    ///     .unwrap();
+    /// ```
+    pub fn overrides<I, S>(mut self, overrides: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.overrides.extend(overrides.into_iter().map(Into::into));
+        self
+    }
+
+    fn env_mut(&mut self) -> &mut EnvOverrides {
+        self.env.get_or_insert_with(|| EnvOverrides {
+            prefix: String::new(),
+            separator: "__".to_string(),
+        })
+    }
+
+    fn vars(&self) -> Vec<(String, String)> {
+        match &self.vars {
+            Some(vars) => vars.clone(),
+            None => env::vars_os()
+                .filter_map(|(name, value)| {
+                    Some((name.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+        }
+    }
+
+    /// Resolve the `${...}` references found in strings once everything has been merged and
+    /// overridden.
+    ///
+    /// `${key.path}` is replaced with the value of another key, such as `${data_dir}` or
+    /// `${servers[0].host}`, keeping its type when it is the whole string. `${env:NAME}` is
+    /// replaced with the value of an environment variable, and `${env:NAME:-default}` falls back
+    /// to `default` when it is unset or empty. `$${` produces a literal `${`.
+    ///
+    /// ```toml
+    /// data_dir = "${env:HOME}/.local/share/app"
+    /// cache_dir = "${data_dir}/cache"
+    /// port = "${env:PORT:-8080}"
+    /// ```
+    pub fn interpolate(mut self) -> Self {
+        self.interpolate = true;
+        self
+    }
+
+    /// Read the keys located at @key_paths, such as `database.password`, from the files their
+    /// secrets are mounted as, such as `/run/secrets/db` with Docker or Kubernetes, once
+    /// everything has been merged and interpolated.
+    ///
+    /// Such a key can be set with a `_file` suffix, `database.password_file = "/run/secrets/db"`
+    /// being replaced with `database.password`, whose value is the trimmed contents of the file,
+    /// taking precedence over any password set inline. Its value can also be a `file:` URI, such
+    /// as `file:///run/secrets/db`. Other keys are left alone, a `log_file` key isn't read and
+    /// a `file:` string elsewhere stays a string.
+    ///
+    /// Errors name the key and the path of the secret which couldn't be read, and the secrets
+    /// are redacted from the messages of deserialization errors.
+    pub fn secret_keys<I, S>(mut self, key_paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.secret_keys
+            .extend(key_paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// Decide what to do with the keys which are present in the files but not used by the
+    /// configuration type, whatever the format of the files.
+    ///
+    /// This works on types which can't be annotated with `#[serde(deny_unknown_fields)]`, such
+    /// as the ones from other crates.
+    pub fn unknown_keys(mut self, policy: UnknownKeys) -> Self {
+        self.unknown_keys = policy;
+        self
+    }
+
+    /// Keep track of where each value comes from: which file and line, or which environment
+    /// variable, see [`ConfigLoader::load_detailed`] and [`Provenance::explain`].
+    ///
+    /// ```rust,no_run
+    /// use config_file::ConfigLoader;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     timeout: u64,
+    /// }
+    ///
+    /// let loaded = ConfigLoader::new()
+    ///     .file("/etc/app/config.toml")
+    ///     .optional_file("/home/user/.config/app/config.toml")
+    ///     .env_prefix("APP")
+    ///     .track_origins()
+    ///     .load_detailed::<Config>()
+    ///     .unwrap();
+    /// if let Some(provenance) = loaded.provenance() {
+    ///     print!("{}", provenance.explain());
+    /// }
+    /// ```
+    pub fn track_origins(mut self) -> Self {
+        self.track_origins = true;
+        self
+    }
+
+    /// Merge all the files, apply the overrides and deserialize the result
+    pub fn load<C: DeserializeOwned>(&self) -> Result<C, ConfigFileError> {
+        self.load_detailed().map(Loaded::into_config)
+    }
+
+    /// Load the configuration like [`ConfigLoader::load`], also returning the unknown keys when
+    /// using [`UnknownKeys::Warn`] and the origin of each value when using
+    /// [`ConfigLoader::track_origins`]
+    pub fn load_detailed<C: DeserializeOwned>(&self) -> Result<Loaded<C>, ConfigFileError> {
+        self.merge()?.deserialize(self.unknown_keys)
+    }
+
+    /// Load the configuration like [`ConfigLoader::load`], checking it against the JSON Schema of
+    /// @C before deserializing it.
+    ///
+    /// All the violations are reported at once as [`ConfigFileError::Validation`], with the
+    /// path of the offending key, whatever the format of the files.
+    #[cfg(feature = "schema")]
+    pub fn load_with_schema<C>(&self) -> Result<C, ConfigFileError>
+    where
+        C: DeserializeOwned + schemars::JsonSchema,
+    {
+        let merged = self.merge()?;
+        let untyped = |key_path: &str| merged.untyped.is_flagged(key_path);
+        crate::schema::validate(&crate::json_schema::<C>(), &merged.value, &untyped)
+            .map_err(|violations| redact(violations, &merged.secrets))?;
+        merged
+            .deserialize(self.unknown_keys)
+            .map(Loaded::into_config)
+    }
+
+    /// Load the configuration like [`ConfigLoader::load`], and then check that it is valid,
+    /// reporting all the violations as [`ConfigFileError::Validation`]
+    pub fn load_validated<C: DeserializeOwned + Validate>(&self) -> Result<C, ConfigFileError> {
+        let merged = self.merge()?;
+        let secrets = merged.secrets.clone();
+        let config = merged.deserialize(self.unknown_keys)?.into_config();
+        Violations::check(&config).map_err(|violations| redact(violations, &secrets))?;
+        Ok(config)
+    }
+
+    /// Merge all the layers, keeping track of where their values come from if asked to
+    fn merge(&self) -> Result<Merged, ConfigFileError> {
+        let mut merged = Value::Table(Default::default());
+        let mut untyped = KeyPathMap::default();
+        let mut provenance = Some(Provenance::default()).filter(|_| self.track_origins);
+        for defaults in &self.defaults {
+            let value = defaults.load()?;
+            untyped.record(String::new(), &value, &mut |_| defaults.is_untyped());
+            if let Some(provenance) = &mut provenance {
+                provenance.record(String::new(), &value, &mut |_| Origin::Default);
+            }
+            merged.merge(value);
+        }
+        // The files are merged apart, their profiles being selected before they are merged
+        let mut files = Value::Table(Default::default());
+        let mut files_untyped = KeyPathMap::default();
+        let mut files_provenance = Some(Provenance::default()).filter(|_| self.track_origins);
+        for layer in &self.files {
+            let value = if layer.dir {
+                self.load_dir(&layer.path, &mut files_untyped, &mut files_provenance)
+            } else {
+                self.load_file(
+                    &layer.path,
+                    &mut files_untyped,
+                    &mut Vec::new(),
+                    &mut files_provenance,
+                )
+            };
+            match value {
+                Ok(value) => files.merge(value),
+                Err(ConfigFileError::FileAccess(err))
+                    if layer.optional && err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if self.profile.is_some() || !self.profiles.is_empty() {
+            let profile = match &self.profile {
+                Some(Profile::Name(name)) => Some(name.clone()),
+                Some(Profile::Env(var)) => self
+                    .vars()
+                    .into_iter()
+                    .find(|(name, value)| name == var && !value.is_empty())
+                    .map(|(_, value)| value),
+                None => None,
+            };
+            files = select_profile(
+                files,
+                &self.profiles,
+                profile.as_deref(),
+                &mut files_untyped,
+                &mut files_provenance,
+            )?;
+        }
+        merged.merge(files);
+        untyped.merge(files_untyped);
+        if let (Some(provenance), Some(files_provenance)) = (&mut provenance, files_provenance) {
+            provenance.merge(files_provenance);
+        }
+        if let Some(env) = &self.env {
+            // The variable selecting the profile isn't meant to override a key
+            let profile_var = match &self.profile {
+                Some(Profile::Env(var)) => Some(var),
+                _ => None,
+            };
+            let vars = self
+                .vars()
+                .into_iter()
+                .filter(|(name, _)| Some(name) != profile_var)
+                .collect();
+            env.apply(&mut merged, vars, &mut untyped, &mut provenance);
+        }
+        for arg in &self.overrides {
+            apply_override(&mut merged, arg, &mut untyped, &mut provenance)?;
+        }
+        if self.interpolate {
+            let vars = self.vars().into_iter().collect::<HashMap<_, _>>();
+            merged = value::interpolate(&merged, |name| vars.get(name).cloned(), &mut untyped)?;
+        }
+        let secrets = secrets::resolve(&mut merged, &self.secret_keys, &mut untyped)?;
+        Ok(Merged {
+            value: merged,
+            untyped,
+            secrets,
+            provenance,
+        })
+    }
+
+    /// Load and merge the fragments of the drop-in directory located at @path, flagging the
+    /// values coming from untyped formats in @untyped
+    fn load_dir(
+        &self,
+        path: &Path,
+        untyped: &mut KeyPathMap<bool>,
+        provenance: &mut Option<Provenance>,
+    ) -> Result<Value, ConfigFileError> {
+        let mut fragments = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if is_fragment(&path) && path.is_file() && self.formats.supports(&path) {
+                fragments.push(path);
+            }
+        }
+        fragments.sort();
+        let mut merged = Value::Table(Default::default());
+        for path in fragments {
+            match self.load_file(&path, untyped, &mut Vec::new(), provenance) {
+                Ok(value) => merged.merge(value),
+                Err(err) => {
+                    return Err(ConfigFileError::Fragment {
+                        path,
+                        source: Box::new(err),
+                    })
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Load the file located at @path along with the files it includes, @chain being the files
+    /// which led to including it.
+    ///
+    /// The values which come from untyped formats are flagged in @untyped, in the order the
+    /// values are merged, and their origins are recorded in @provenance.
+    fn load_file(
+        &self,
+        path: &Path,
+        untyped: &mut KeyPathMap<bool>,
+        chain: &mut Vec<PathBuf>,
+        provenance: &mut Option<Provenance>,
+    ) -> Result<Value, ConfigFileError> {
+        let mut value = self.formats.load::<Value, _>(path)?;
+        let xml = self.formats.is_untyped(path);
+        let includes = match (&self.include_key, &mut value) {
+            (Some(key), Value::Table(table)) => table.remove(key),
+            _ => None,
+        };
+        let includes = match includes {
+            // A single file doesn't need to be put in a list
+            Some(Value::String(include)) => vec![include],
+            Some(includes) => value::from_value::<Vec<String>>(includes, &|_| xml)?,
+            None => {
+                untyped.record(String::new(), &value, &mut |_| xml);
+                track_file(provenance, path, &value);
+                return Ok(value);
+            }
+        };
+        let canonical = path.canonicalize()?;
+        if chain.contains(&canonical) {
+            chain.push(canonical);
+            return Err(ConfigFileError::RecursiveInclude(chain.clone()));
+        }
+        chain.push(canonical);
+        let mut merged = Value::Table(Default::default());
+        for include in resolve_includes(path, &includes)? {
+            merged.merge(self.load_file(&include, untyped, chain, provenance)?);
+        }
+        chain.pop();
+        untyped.record(String::new(), &value, &mut |_| xml);
+        track_file(provenance, path, &value);
+        merged.merge(value);
+        Ok(merged)
+    }
+}
+
+/// The layers of a configuration merged together, not deserialized yet
+struct Merged {
+    value: Value,
+    /// The values coming from untyped formats, which are converted to the expected types
+    untyped: KeyPathMap<bool>,
+    /// The secrets read from files, redacted from the errors
+    secrets: Vec<String>,
+    /// Where the values come from, when tracking origins
+    provenance: Option<Provenance>,
+}
+
+impl Merged {
+    /// Deserialize a @C out of ourselves, handling its unknown keys according to @policy
+    fn deserialize<C: DeserializeOwned>(
+        self,
+        policy: UnknownKeys,
+    ) -> Result<Loaded<C>, ConfigFileError> {
+        let mut provenance = self.provenance;
+        if let Some(provenance) = &mut provenance {
+            provenance.set_values(&self.value, &self.secrets);
+        }
+        let untyped = self.untyped;
+        let untyped = |key_path: &str| untyped.is_flagged(key_path);
+        let (config, unknown_keys) = match policy {
+            UnknownKeys::Ignore => (value::from_value(self.value, &untyped), Vec::new()),
+            UnknownKeys::Warn | UnknownKeys::Deny => {
+                match value::from_value_reporting_unknown(self.value, &untyped) {
+                    Ok((config, unknown_keys)) => (Ok(config), unknown_keys),
+                    Err(err) => (Err(err), Vec::new()),
+                }
+            }
+        };
+        let config = config.map_err(|err| err.redact(&self.secrets))?;
+        if policy == UnknownKeys::Deny && !unknown_keys.is_empty() {
+            return Err(ConfigFileError::UnknownKeys(unknown_keys));
+        }
+        Ok(Loaded {
+            config,
+            unknown_keys,
+            provenance,
+        })
+    }
+}
+
+/// Merge the @profile table of @files, if any, on top of the `default` one, on top of the
+/// top-level keys which aren't one of the @declared profiles
+fn select_profile(
+    files: Value,
+    declared: &[String],
+    profile: Option<&str>,
+    untyped: &mut KeyPathMap<bool>,
+    provenance: &mut Option<Provenance>,
+) -> Result<Value, ConfigFileError> {
+    let is_profile = |key: &str| key == DEFAULT_PROFILE || declared.iter().any(|p| p == key);
+    let (mut profiles, common): (BTreeMap<_, _>, BTreeMap<_, _>) = match files {
+        Value::Table(table) => table
+            .into_iter()
+            .partition(|(key, value)| is_profile(key) && matches!(value, Value::Table(_))),
+        _ => Default::default(),
+    };
+    if let Some(profile) = profile.filter(|profile| !is_profile(profile)) {
+        return Err(ConfigFileError::UnknownProfile {
+            profile: profile.to_string(),
+            available: std::iter::once(DEFAULT_PROFILE.to_string())
+                .chain(declared.iter().cloned())
+                .collect(),
+        });
+    }
+    let common_keys = common.keys().map(String::as_str).collect::<Vec<_>>();
+    let mut tables = vec![DEFAULT_PROFILE];
+    tables.extend(profile.filter(|profile| profiles.contains_key(*profile)));
+    untyped.select(&common_keys, &tables);
+    if let Some(provenance) = provenance {
+        provenance.select(&common_keys, &tables);
+    }
+    let mut selected = Value::Table(common);
+    if let Some(value) = profiles.remove(DEFAULT_PROFILE) {
+        selected.merge(value);
+    }
+    if let Some(value) = profile.and_then(|profile| profiles.remove(profile)) {
+        selected.merge(value);
+    }
+    Ok(selected)
+}
+
+/// Apply @arg, of the form `key.path=value`, to @merged, flagging it in @untyped since it is a
+/// plain string
+fn apply_override(
+    merged: &mut Value,
+    arg: &str,
+    untyped: &mut KeyPathMap<bool>,
+    provenance: &mut Option<Provenance>,
+) -> Result<(), ConfigFileError> {
+    let error = |reason: String| ConfigFileError::Override {
+        arg: arg.to_string(),
+        reason,
+    };
+    let (key_path, value) = arg
+        .split_once('=')
+        .ok_or_else(|| error("expected key.path=value".to_string()))?;
+    let key_path = key_path.trim();
+    let segments = value::parse_key_path(key_path)
+        .filter(|segments| !segments.is_empty())
+        .ok_or_else(|| error(format!("invalid key path {}", key_path)))?;
+    let value = Value::parse_override(value);
+    untyped.record(key_path.to_string(), &value, &mut |_| true);
+    if let Some(provenance) = provenance {
+        provenance.record(key_path.to_string(), &value, &mut |_| Origin::Cli);
+    }
+    merged.set_segments(&segments, value).map_err(error)
+}
+
+/// Record that the values of @value come from the file located at @path
+fn track_file(provenance: &mut Option<Provenance>, path: &Path, value: &Value) {
+    if let Some(provenance) = provenance {
+        // Only used to guess the lines, the file has already been successfully read
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        provenance.record(String::new(), value, &mut |key_path| Origin::File {
+            path: path.to_path_buf(),
+            line: find_line(&contents, key_path),
+        });
+    }
+}
+
+fn redact(violations: Vec<Violation>, secrets: &[String]) -> ConfigFileError {
+    ConfigFileError::Validation(
+        violations
+            .into_iter()
+            .map(|violation| violation.redact(secrets))
+            .collect(),
+    )
+}
+
+/// Whether the file located at @path is part of a drop-in directory, and not hidden or a backup
+fn is_fragment(path: &Path) -> bool {
+    const BACKUPS: &[&str] = &[
+        "~",
+        ".bak",
+        ".orig",
+        ".swp",
+        ".dpkg-old",
+        ".dpkg-new",
+        ".dpkg-dist",
+        ".rpmnew",
+        ".rpmsave",
+    ];
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => {
+            !name.starts_with('.') && !BACKUPS.iter().any(|suffix| name.ends_with(suffix))
+        }
+        None => false,
+    }
+}
+
+/// The files listed in @includes, relative to the file located at @from
+fn resolve_includes(from: &Path, includes: &[String]) -> Result<Vec<PathBuf>, ConfigFileError> {
+    let dir = from.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = Vec::new();
+    for include in includes {
+        let path = dir.join(include);
+        // Escape the directory so that only the include itself is a pattern
+        let pattern = match dir.to_str() {
+            Some(dir) if Path::new(include).is_relative() => {
+                Path::new(&glob::Pattern::escape(dir)).join(include)
+            }
+            _ => path.clone(),
+        };
+        match pattern.to_str().map(glob::glob) {
+            // Patterns matching nothing are fine, such directories are often empty
+            Some(Ok(matches)) if include.contains(['*', '?', '[']) => {
+                files.extend(matches.filter_map(Result::ok).filter(|path| path.is_file()))
+            }
+            _ if path.is_file() => files.push(path),
+            _ => {
+                return Err(ConfigFileError::IncludeNotFound {
+                    path,
+                    from: from.to_path_buf(),
+                })
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct TestConfig {
+        host: String,
+        port: u16,
+        inner: TestConfigInner,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct TestConfigInner {
+        answer: u8,
+        question: Option<String>,
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.toml");
+        let user = dir.path().join("user.toml");
+        std::fs::write(
+            &system,
+            "host = \"localhost\"\nport = 80\n[inner]\nanswer = 41\n",
+        )
+        .unwrap();
+        std::fs::write(&user, "port = 443\n[inner]\nanswer = 42\n").unwrap();
+        let config: TestConfig = ConfigLoader::new()
+            .file(&system)
+            .optional_file(dir.path().join("missing.toml"))
+            .optional_file(&user)
+            .load()
+            .unwrap();
+        assert_eq!(
+            config,
+            TestConfig {
+                host: "localhost".to_string(),
+                port: 443,
+                inner: TestConfigInner {
+                    answer: 42,
+                    question: None,
+                },
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_missing_required() {
+        let res = ConfigLoader::new()
+            .file("/tmp/foobar.toml")
+            .load::<TestConfig>();
+        assert!(matches!(res, Err(ConfigFileError::FileAccess(_))));
+    }
+
+    #[test]
+    fn test_invalid_merged() {
+        let res = ConfigLoader::new().load::<TestConfig>();
+        assert!(matches!(res, Err(ConfigFileError::Value(_))));
+        let res = ConfigLoader::new()
+            .env_prefix("APP")
+            .env_vars([
+                ("APP_HOST", "example.com"),
+                ("APP_PORT", "443"),
+                ("APP_INNER__ANSWER", "many"),
+            ])
+            .load::<TestConfig>();
+        match res {
+            Err(err) => {
+                assert_eq!(err.key_path(), Some("inner.answer"));
+                assert!(matches!(&err, ConfigFileError::Value(err)
+                    if err.to_string() == "inner.answer: invalid type: string \"many\", expected u8"));
+            }
+            Ok(config) => panic!("unexpected config: {:?}", config),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "xml"))]
+    fn test_typed_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.toml");
+        let user = dir.path().join("user.toml");
+        let env = dir.path().join("env.xml");
+        std::fs::write(&system, "host = \"localhost\"\n[inner]\nanswer = 42\n").unwrap();
+        std::fs::write(&user, "port = \"443\"\n").unwrap();
+        std::fs::write(&env, "<config><port>443</port></config>").unwrap();
+        let res = ConfigLoader::new()
+            .file(&system)
+            .file(&user)
+            .load::<TestConfig>();
+        assert!(matches!(res, Err(ConfigFileError::Value(_))));
+        let config: TestConfig = ConfigLoader::new()
+            .file(&system)
+            .file(&user)
+            .file(&env)
+            .load()
+            .unwrap();
+        assert_eq!(config.port, 443);
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_unsigned() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Big {
+            id: u64,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(&path, "{ \"id\": 18446744073709551615 }").unwrap();
+        let config: Big = ConfigLoader::new().file(&path).load().unwrap();
+        assert_eq!(config.id, u64::MAX);
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.cfg");
+        std::fs::write(
+            &system,
+            "host = \"localhost\"\nport = 80\n[inner]\nanswer = 42\n",
+        )
+        .unwrap();
+        let loader = ConfigLoader::new().file(&system);
+        assert!(matches!(
+            loader.load::<TestConfig>(),
+            Err(ConfigFileError::UnsupportedFormat)
+        ));
+        let registry = FormatRegistry::new().register(["cfg"], crate::ConfigFormat::Toml);
+        let config: TestConfig = loader.formats(registry).load().unwrap();
+        assert_eq!(config.inner.answer, 42);
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_env() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct EnvConfig {
+            host: String,
+            port: u16,
+            debug: bool,
+            tags: Vec<String>,
+            inner: TestConfigInner,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "host = \"localhost\"\nport = 80\ndebug = false\ntags = []\n[inner]\nanswer = 41\n",
+        )
+        .unwrap();
+        let config: EnvConfig = ConfigLoader::new()
+            .file(&path)
+            .env_prefix("APP")
+            .env_vars([
+                ("APP_PORT", "443"),
+                ("APP_DEBUG", "true"),
+                ("APP_TAGS", "[example, \"test\"]"),
+                ("APP_INNER__ANSWER", "42"),
+                ("APP_INNER__QUESTION", "why"),
+                ("OTHER_HOST", "example.com"),
+            ])
+            .load()
+            .unwrap();
+        assert_eq!(
+            config,
+            EnvConfig {
+                host: "localhost".to_string(),
+                port: 443,
+                debug: true,
+                tags: vec!["example".to_string(), "test".to_string()],
+                inner: TestConfigInner {
+                    answer: 42,
+                    question: Some("why".to_string()),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_env_separator() {
+        let config: TestConfig = ConfigLoader::new()
+            .env_prefix("APP")
+            .env_separator("_")
+            .env_vars([
+                ("APP_HOST", "example.com"),
+                ("APP_PORT", "443"),
+                ("APP_INNER_ANSWER", "42"),
+            ])
+            .load()
+            .unwrap();
+        assert_eq!(config.host, "example.com");
+        assert_eq!(config.port, 443);
+        assert_eq!(config.inner.answer, 42);
+    }
+
+    #[test]
+    fn test_env_types() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Config {
+            password: String,
+            version: String,
+            code: String,
+            port: u16,
+            ratio: f64,
+            enabled: bool,
+            tags: Vec<String>,
+        }
+
+        let config: Config = ConfigLoader::new()
+            .env_prefix("APP")
+            .env_vars([
+                ("APP_PASSWORD", "12345"),
+                ("APP_VERSION", "1.10"),
+                ("APP_CODE", "007"),
+                ("APP_PORT", "443"),
+                ("APP_RATIO", "0.5"),
+                ("APP_ENABLED", "true"),
+                ("APP_TAGS", "[1, true]"),
+            ])
+            .load()
+            .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                password: "12345".to_string(),
+                version: "1.10".to_string(),
+                code: "007".to_string(),
+                port: 443,
+                ratio: 0.5,
+                enabled: true,
+                tags: vec!["1".to_string(), "true".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    fn test_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let user = dir.path().join("user.yml");
+        std::fs::write(
+            &path,
+            "port = 80\n\n[inner]\nquestion = \"why\"\nanswer = 40\n\n\
+             [default]\nhost = \"localhost\"\n\n[default.inner]\nanswer = 41\n\n\
+             [staging]\nhost = \"staging.example.com\"\n\n\
+             [production]\nhost = \"example.com\"\nport = 443\n",
+        )
+        .unwrap();
+        std::fs::write(&user, "production:\n  inner:\n    answer: 42\n").unwrap();
+        let loader = ConfigLoader::new()
+            .file(&path)
+            .profiles(["staging", "production"]);
+
+        assert!(ConfigLoader::new()
+            .file(&path)
+            .load::<TestConfig>()
+            .is_err());
+        let config: TestConfig = loader
+            .clone()
+            .profile_env("APP_PROFILE")
+            .env_prefix("APP")
+            .env_vars([("APP_PROFILE", "")])
+            .unknown_keys(UnknownKeys::Deny)
+            .load()
+            .unwrap();
+        assert_eq!(
+            config,
+            TestConfig {
+                host: "localhost".to_string(),
+                port: 80,
+                inner: TestConfigInner {
+                    answer: 41,
+                    question: Some("why".to_string()),
+                },
+            }
+        );
+
+        let loaded = loader
+            .clone()
+            .file(&user)
+            .profile_env("APP_PROFILE")
+            .env_prefix("APP")
+            .env_vars([("APP_PROFILE", "production")])
+            .unknown_keys(UnknownKeys::Deny)
+            .track_origins()
+            .load_detailed::<TestConfig>()
+            .unwrap();
+        let config = loaded.config();
+        assert_eq!(config.host, "example.com");
+        assert_eq!(config.port, 443);
+        assert_eq!(config.inner.answer, 42);
+        assert_eq!(config.inner.question.as_deref(), Some("why"));
+        let provenance = loaded.provenance().unwrap();
+        assert_eq!(
+            provenance.origin("port"),
+            Some(&Origin::File {
+                path: path.clone(),
+                line: Some(18),
+            })
+        );
+        assert_eq!(
+            provenance.origin("inner.answer"),
+            Some(&Origin::File {
+                path: user.clone(),
+                line: Some(3),
+            })
+        );
+        assert_eq!(
+            provenance.origin("inner.question"),
+            Some(&Origin::File {
+                path: path.clone(),
+                line: Some(4),
+            })
+        );
+        assert_eq!(provenance.origin("production.port"), None);
+
+        let config: TestConfig = crate::FromConfigFile::from_config_file_with_profile(
+            &path,
+            ["staging", "production"],
+            "staging",
+        )
+        .unwrap();
+        assert_eq!(config.host, "staging.example.com");
+        assert_eq!(config.port, 80);
+        let loaded = loader
+            .clone()
+            .profile("staging")
+            .track_origins()
+            .load_detailed::<TestConfig>()
+            .unwrap();
+        assert_eq!(
+            loaded.provenance().unwrap().origin("port"),
+            Some(&Origin::File {
+                path: path.clone(),
+                line: Some(1),
+            })
+        );
+
+        for (loader, message) in [
+            (
+                loader.profile("prod"),
+                "unknown profile prod, available profiles: default, staging, production",
+            ),
+            (
+                ConfigLoader::new().file(&path).profile("staging"),
+                "unknown profile staging, available profiles: default",
+            ),
+        ] {
+            let err = loader.load::<TestConfig>().unwrap_err();
+            assert!(matches!(err, ConfigFileError::UnknownProfile { .. }));
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_defaults() {
+        use crate::FromConfigFile;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Config {
+            host: String,
+            port: u16,
+            tags: Vec<String>,
+        }
+
+        impl Default for Config {
+            fn default() -> Self {
+                Self {
+                    host: "localhost".to_string(),
+                    port: 80,
+                    tags: vec!["default".to_string()],
+                }
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "port = 443\n").unwrap();
+        let config: Config = ConfigLoader::new()
+            .file(&path)
+            .defaults(&Config::default())
+            .load()
+            .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                port: 443,
+                ..Config::default()
+            }
+        );
+        assert_eq!(
+            Config::from_config_file_with_defaults(&path).unwrap(),
+            config
+        );
+
+        let loaded = ConfigLoader::new()
+            .defaults(&Config::default())
+            .defaults_str("host = \"example.com\"", ConfigFormat::Toml)
+            .file(&path)
+            .track_origins()
+            .load_detailed::<Config>()
+            .unwrap();
+        assert_eq!(loaded.config().host, "example.com");
+        assert_eq!(loaded.config().port, 443);
+        let provenance = loaded.provenance().unwrap();
+        assert_eq!(provenance.origin("host"), Some(&Origin::Default));
+        assert_eq!(provenance.origin("tags").unwrap().to_string(), "defaults");
+
+        let err = ConfigLoader::new()
+            .defaults_str("port = ", ConfigFormat::Toml)
+            .file(&path)
+            .load::<Config>()
+            .unwrap_err();
+        assert!(matches!(err, ConfigFileError::Parse(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_overrides() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Server {
+            host: String,
+            port: u16,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Config {
+            pool_size: u32,
+            password: String,
+            tags: Vec<String>,
+            matrix: Vec<Vec<u8>>,
+            servers: Vec<Server>,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "pool_size = 5\ntags = []\n\n[[servers]]\nhost = \"a\"\nport = 80\n\n[[servers]]\nhost = \"b\"\nport = 80\n",
+        )
+        .unwrap();
+        let loader = ConfigLoader::new()
+            .file(&path)
+            .env_prefix("APP")
+            .env_vars([("APP_POOL_SIZE", "10")])
+            .overrides(["pool_size=20", r#"tags=["a,b", c]"#, "servers[1].host = c"])
+            .overrides(["password=007", "matrix=[[1,2],[3]]"])
+            .overrides(["servers[2].host=d", "servers[2].port=443"])
+            .track_origins();
+        let loaded = loader.load_detailed::<Config>().unwrap();
+        assert_eq!(
+            loaded.config(),
+            &Config {
+                pool_size: 20,
+                password: "007".to_string(),
+                tags: vec!["a,b".to_string(), "c".to_string()],
+                matrix: vec![vec![1, 2], vec![3]],
+                servers: vec![
+                    Server {
+                        host: "a".to_string(),
+                        port: 80,
+                    },
+                    Server {
+                        host: "c".to_string(),
+                        port: 80,
+                    },
+                    Server {
+                        host: "d".to_string(),
+                        port: 443,
+                    },
+                ],
+            }
+        );
+        let provenance = loaded.provenance().unwrap();
+        assert_eq!(provenance.origin("pool_size"), Some(&Origin::Cli));
+        assert_eq!(provenance.origin("servers[1].host"), Some(&Origin::Cli));
+        assert!(matches!(
+            provenance.origin("servers[1].port"),
+            Some(Origin::File { .. })
+        ));
+
+        for (arg, message) in [
+            (
+                "pool_size",
+                "invalid override \"pool_size\": expected key.path=value",
+            ),
+            ("=20", "invalid override \"=20\": invalid key path "),
+            (
+                "servers[5].host=e",
+                "invalid override \"servers[5].host=e\": index 5 is out of bounds for 2 elements",
+            ),
+        ] {
+            let err = ConfigLoader::new()
+                .file(&path)
+                .overrides([arg])
+                .load::<Config>()
+                .unwrap_err();
+            assert!(matches!(err, ConfigFileError::Override { .. }));
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml", feature = "json", feature = "xml"))]
+    fn test_single_file() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct FullConfig {
+            host: String,
+            port: u16,
+            tags: Vec<String>,
+            inner: TestConfigInner,
+        }
+
+        for file in ["config.json", "config.toml", "config.xml", "config.yml"] {
+            let config: FullConfig = ConfigLoader::new()
+                .file(format!("testdata/{}", file))
+                .load()
+                .unwrap();
+            assert_eq!(config.host, "example.com");
+            assert_eq!(config.port, 443);
+            assert_eq!(config.tags, vec!["example", "test"]);
+            assert_eq!(config.inner.answer, 42);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml", feature = "json", feature = "xml"))]
+    fn test_mixed_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.toml");
+        let user = dir.path().join("user.yml");
+        let local = dir.path().join("local.json");
+        let env = dir.path().join("env.xml");
+        std::fs::write(&system, "host = \"localhost\"\nport = 80\n").unwrap();
+        std::fs::write(&user, "inner:\n  answer: 41\n  question: why\n").unwrap();
+        std::fs::write(&local, "{ \"inner\": { \"answer\": 42 } }").unwrap();
+        std::fs::write(&env, "<config><port>443</port></config>").unwrap();
+        let config: TestConfig = ConfigLoader::new()
+            .file(&system)
+            .file(&user)
+            .file(&local)
+            .file(&env)
+            .load()
+            .unwrap();
+        assert_eq!(
+            config,
+            TestConfig {
+                host: "localhost".to_string(),
+                port: 443,
+                inner: TestConfigInner {
+                    answer: 42,
+                    question: Some("why".to_string()),
+                },
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    fn test_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.toml");
+        std::fs::create_dir(dir.path().join("conf.d")).unwrap();
+        std::fs::write(
+            &base,
+            "include = [\"db.toml\", \"conf.d/*.yaml\"]\nport = 443\n[inner]\nanswer = 42\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("db.toml"),
+            "include = \"conf.d/*.toml\"\nhost = \"localhost\"\nport = 80\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("conf.d/10-host.yaml"), "host: example.com").unwrap();
+        std::fs::write(dir.path().join("conf.d/20-host.yaml"), "host: example.org").unwrap();
+        std::fs::write(
+            dir.path().join("conf.d/inner.toml"),
+            "[inner]\nanswer = 41\nquestion = \"why\"\n",
+        )
+        .unwrap();
+
+        let loader = ConfigLoader::new().file(&base);
+        assert!(matches!(
+            loader.load::<TestConfig>(),
+            Err(ConfigFileError::Value(_))
+        ));
+        let config: TestConfig = loader.includes("include").load().unwrap();
+        assert_eq!(
+            config,
+            TestConfig {
+                host: "example.org".to_string(),
+                port: 443,
+                inner: TestConfigInner {
+                    answer: 42,
+                    question: Some("why".to_string()),
+                },
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_include_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.toml");
+        let other = dir.path().join("other.toml");
+        std::fs::write(&base, "include = [\"missing.toml\"]\n").unwrap();
+        let loader = ConfigLoader::new().file(&base).includes("include");
+        match loader.load::<TestConfig>() {
+            Err(ConfigFileError::IncludeNotFound { path, from }) => {
+                assert_eq!(path, dir.path().join("missing.toml"));
+                assert_eq!(from, base);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        std::fs::write(&base, "include = [\"other.toml\"]\n").unwrap();
+        std::fs::write(&other, "include = [\"base.toml\"]\n").unwrap();
+        match loader.load::<TestConfig>() {
+            Err(ConfigFileError::RecursiveInclude(chain)) => {
+                let base = base.canonicalize().unwrap();
+                let other = other.canonicalize().unwrap();
+                assert_eq!(chain, vec![base.clone(), other, base]);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_is_fragment() {
+        assert!(is_fragment(Path::new("/etc/app/conf.d/10-db.toml")));
+        assert!(!is_fragment(Path::new("/etc/app/conf.d/10-db.toml~")));
+        assert!(!is_fragment(Path::new(
+            "/etc/app/conf.d/10-db.toml.dpkg-old"
+        )));
+        assert!(!is_fragment(Path::new("/etc/app/conf.d/.10-db.toml")));
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    fn test_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("config.toml");
+        let conf_d = dir.path().join("conf.d");
+        std::fs::create_dir(&conf_d).unwrap();
+        std::fs::write(&main, "host = \"localhost\"\nport = 80\n").unwrap();
+        std::fs::write(conf_d.join("10-port.toml"), "port = 443\n").unwrap();
+        std::fs::write(conf_d.join("20-inner.yml"), "inner:\n  answer: 41").unwrap();
+        std::fs::write(conf_d.join("30-inner.toml"), "[inner]\nanswer = 42\n").unwrap();
+        std::fs::write(conf_d.join("30-inner.toml~"), "[inner]\nanswer = 1\n").unwrap();
+        std::fs::write(conf_d.join("40.toml.dpkg-old"), "port = 1\n").unwrap();
+        std::fs::write(conf_d.join("README"), "Drop files here").unwrap();
+
+        let config: TestConfig = ConfigLoader::new()
+            .file(&main)
+            .dir(&conf_d)
+            .optional_dir(dir.path().join("missing.d"))
+            .load()
+            .unwrap();
+        assert_eq!(
+            config,
+            TestConfig {
+                host: "localhost".to_string(),
+                port: 443,
+                inner: TestConfigInner {
+                    answer: 42,
+                    question: None,
+                },
+            }
+        );
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Fragments {
+            port: u16,
+            inner: TestConfigInner,
+        }
+        let fragments = <Fragments as crate::FromConfigFile>::from_config_dir(&conf_d).unwrap();
+        assert_eq!(fragments.port, 443);
+        assert_eq!(fragments.inner.answer, 42);
+
+        let broken = conf_d.join("25-broken.toml");
+        std::fs::write(&broken, "[inner\n").unwrap();
+        match ConfigLoader::new().dir(&conf_d).load::<TestConfig>() {
+            Err(ConfigFileError::Fragment { path, source }) => {
+                assert_eq!(path, broken);
+                assert!(matches!(*source, ConfigFileError::Parse(_)));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(matches!(
+            ConfigLoader::new()
+                .dir(dir.path().join("missing.d"))
+                .load::<TestConfig>(),
+            Err(ConfigFileError::FileAccess(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml", feature = "json", feature = "xml"))]
+    fn test_interpolate() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct PathsConfig {
+            data_dir: String,
+            cache_dir: String,
+            port: u16,
+            tls_port: u16,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "config.toml",
+                "data_dir = \"${env:HOME}/data\"\ncache_dir = \"${data_dir}/cache\"\nport = \"${env:PORT:-8080}\"\ntls_port = \"${port}\"\n",
+            ),
+            (
+                "config.yml",
+                "data_dir: ${env:HOME}/data\ncache_dir: ${data_dir}/cache\nport: ${env:PORT:-8080}\ntls_port: ${port}\n",
+            ),
+            (
+                "config.json",
+                "{ \"data_dir\": \"${env:HOME}/data\", \"cache_dir\": \"${data_dir}/cache\", \"port\": \"${env:PORT:-8080}\", \"tls_port\": \"${port}\" }",
+            ),
+            (
+                "config.xml",
+                "<config><data_dir>${env:HOME}/data</data_dir><cache_dir>${data_dir}/cache</cache_dir><port>${env:PORT:-8080}</port><tls_port>${port}</tls_port></config>",
+            ),
+        ];
+        for (file, contents) in files {
+            let path = dir.path().join(file);
+            std::fs::write(&path, contents).unwrap();
+            let loader = ConfigLoader::new()
+                .file(&path)
+                .env_vars([("HOME", "/home/user")])
+                .interpolate();
+            assert_eq!(
+                loader.load::<PathsConfig>().unwrap(),
+                PathsConfig {
+                    data_dir: "/home/user/data".to_string(),
+                    cache_dir: "/home/user/data/cache".to_string(),
+                    port: 8080,
+                    tls_port: 8080,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_interpolation_cycle() {
+        let res = ConfigLoader::new()
+            .env_prefix("APP")
+            .env_vars([("APP_HOST", "${port}"), ("APP_PORT", "${host}")])
+            .interpolate()
+            .load::<TestConfig>();
+        match res {
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "couldn't interpolate host: reference cycle: host -> port -> host"
+            ),
+            Ok(config) => panic!("unexpected config: {:?}", config),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let host = dir.path().join("host");
+        let port = dir.path().join("port");
+        std::fs::write(&host, "example.com\n").unwrap();
+        std::fs::write(&port, "hunter2\n").unwrap();
+        std::fs::write(
+            &path,
+            format!(
+                "host_file = \"{}\"\nport = \"file://{}\"\n[inner]\nanswer = 42\nquestion_file = \"/missing\"\n",
+                host.display(),
+                port.display()
+            ),
+        )
+        .unwrap();
+        let loader = ConfigLoader::new()
+            .file(&path)
+            .secret_keys(["host", "port"]);
+        match loader.load::<TestConfig>() {
+            Err(err) => {
+                assert_eq!(err.key_path(), Some("port"));
+                assert!(matches!(&err, ConfigFileError::Value(err)
+                    if err.to_string() == "port: invalid type: string \"<redacted>\", expected u16"));
+            }
+            Ok(config) => panic!("unexpected config: {:?}", config),
+        }
+
+        std::fs::write(&port, "443").unwrap();
+        let config = loader.load::<TestConfig>().unwrap();
+        assert_eq!(config.host, "example.com");
+        assert_eq!(config.port, 443);
+        assert_eq!(config.inner.question, None);
+
+        // Multi-line secrets are quoted with escapes in the messages
+        std::fs::write(&port, "hunter2\nhunter3\n").unwrap();
+        match loader.load::<TestConfig>() {
+            Err(err) => assert!(matches!(&err, ConfigFileError::Value(err)
+                if err.to_string() == "port: invalid type: string \"<redacted>\", expected u16")),
+            Ok(config) => panic!("unexpected config: {:?}", config),
+        }
+        std::fs::write(&port, "443").unwrap();
+
+        std::fs::remove_file(&host).unwrap();
+        assert!(matches!(
+            loader.load::<TestConfig>(),
+            Err(ConfigFileError::Secret { key_path, path, .. }) if key_path == "host_file" && path == host
+        ));
+    }
+
+    impl Validate for TestConfig {
+        fn validate(&self, violations: &mut Violations) {
+            if self.port == 0 {
+                violations.add("port", "must be in 1..=65535");
+            }
+            violations.nested("inner", &self.inner);
+        }
+    }
+
+    impl Validate for TestConfigInner {
+        fn validate(&self, violations: &mut Violations) {
+            if self.answer != 42 {
+                violations.add("answer", format!("{} is not the answer", self.answer));
+            }
+        }
+    }
+
+    #[test]
+    fn test_validated() {
+        let loader = ConfigLoader::new().env_prefix("APP").env_vars([
+            ("APP_HOST", "example.com"),
+            ("APP_PORT", "0"),
+            ("APP_INNER__ANSWER", "41"),
+        ]);
+        match loader.load_validated::<TestConfig>() {
+            Err(ConfigFileError::Validation(violations)) => {
+                assert_eq!(violations.len(), 2);
+                assert_eq!(violations[1].key_path(), Some("inner.answer"));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            loader
+                .load_validated::<TestConfig>()
+                .unwrap_err()
+                .to_string(),
+            "invalid configuration: port: must be in 1..=65535, inner.answer: 41 is not the answer"
+        );
+        let config = loader
+            .env_vars([
+                ("APP_HOST", "example.com"),
+                ("APP_PORT", "443"),
+                ("APP_INNER__ANSWER", "42"),
+            ])
+            .load_validated::<TestConfig>()
+            .unwrap();
+        assert_eq!(config.port, 443);
+    }
+
+    #[test]
+    #[cfg(all(feature = "schema", feature = "yaml"))]
+    fn test_schema() {
+        #[derive(Debug, Deserialize, schemars::JsonSchema)]
+        struct SchemaConfig {
+            host: String,
+            port: u16,
+            tags: Vec<String>,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yml");
+        std::fs::write(&path, "host: [example.com]\nport: -1\ntags: [a, b]\n").unwrap();
+        match ConfigLoader::new()
+            .file(&path)
+            .load_with_schema::<SchemaConfig>()
+        {
+            Err(ConfigFileError::Validation(violations)) => assert_eq!(
+                violations
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+                vec![
+                    "host: expected string, found array",
+                    "port: must be at least 0"
+                ]
+            ),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        std::fs::write(&path, "host: example.com\nport: 443\ntags: [a, b]\n").unwrap();
+        let config: SchemaConfig = ConfigLoader::new().file(&path).load_with_schema().unwrap();
+        assert_eq!(config.host, "example.com");
+        assert_eq!(config.port, 443);
+        assert_eq!(config.tags, vec!["a", "b"]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml", feature = "json", feature = "xml"))]
+    fn test_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "config.toml",
+                "host = \"localhost\"\nport = 80\nprot = 8080\n[inner]\nanswer = 42\nquestoin = \"why\"\n",
+            ),
+            (
+                "config.yml",
+                "host: localhost\nport: 80\nprot: 8080\ninner:\n  answer: 42\n  questoin: why\n",
+            ),
+            (
+                "config.json",
+                "{ \"host\": \"localhost\", \"port\": 80, \"prot\": 8080, \"inner\": { \"answer\": 42, \"questoin\": \"why\" } }",
+            ),
+            (
+                "config.xml",
+                "<config><host>localhost</host><port>80</port><prot>8080</prot><inner><answer>42</answer><questoin>why</questoin></inner></config>",
+            ),
+        ];
+        for (file, contents) in files {
+            let path = dir.path().join(file);
+            std::fs::write(&path, contents).unwrap();
+            let loader = ConfigLoader::new().file(&path);
+            assert!(loader
+                .load_detailed::<TestConfig>()
+                .unwrap()
+                .unknown_keys()
+                .is_empty());
+
+            let loaded = loader
+                .clone()
+                .unknown_keys(UnknownKeys::Warn)
+                .load_detailed::<TestConfig>()
+                .unwrap();
+            assert_eq!(loaded.unknown_keys(), ["inner.questoin", "prot"]);
+            assert_eq!(loaded.into_config().port, 80);
+
+            match loader.unknown_keys(UnknownKeys::Deny).load::<TestConfig>() {
+                Err(err) => assert_eq!(
+                    err.to_string(),
+                    "unknown keys in config: inner.questoin, prot"
+                ),
+                Ok(config) => panic!("unexpected config: {:?}", config),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    fn test_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.toml");
+        let user = dir.path().join("user.yml");
+        std::fs::write(
+            &system,
+            "host = \"localhost\"\nport = 80\n\n[inner]\nanswer = 41\n",
+        )
+        .unwrap();
+        std::fs::write(&user, "inner:\n  question: why\n  answer: 42\n").unwrap();
+        let loader = ConfigLoader::new()
+            .file(&system)
+            .file(&user)
+            .env_prefix("APP")
+            .env_vars([("APP_PORT", "443")]);
+        assert!(loader
+            .load_detailed::<TestConfig>()
+            .unwrap()
+            .provenance()
+            .is_none());
+
+        let loaded = loader
+            .track_origins()
+            .load_detailed::<TestConfig>()
+            .unwrap();
+        let provenance = loaded.provenance().unwrap();
+        assert_eq!(
+            provenance.origin("host"),
+            Some(&Origin::File {
+                path: system.clone(),
+                line: Some(1),
+            })
+        );
+        assert_eq!(
+            provenance.origin("inner.answer"),
+            Some(&Origin::File {
+                path: user.clone(),
+                line: Some(3),
+            })
+        );
+        assert_eq!(
+            provenance.explain(),
+            format!(
+                "host = \"localhost\"  # {system}:1\ninner.answer = 42  # {user}:3\ninner.question = \"why\"  # {user}:2\nport = \"443\"  # environment variable APP_PORT\n",
+                system = system.display(),
+                user = user.display()
+            )
+        );
+    }
+}
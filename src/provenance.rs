@@ -0,0 +1,286 @@
+use crate::{
+    key_path::{join, redact, KeyPathMap},
+    value::Value,
+};
+use std::{collections::BTreeMap, fmt, path::PathBuf};
+
+/// Where a configuration value comes from
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Origin {
+    /// The defaults, see [`ConfigLoader::defaults`](crate::ConfigLoader::defaults)
+    Default,
+    /// A configuration file
+    File {
+        /// The path of the file
+        path: PathBuf,
+        /// The line where the key is set, starting from 1, as a best effort guess
+        line: Option<usize>,
+    },
+    /// An environment variable, see [`ConfigLoader::env_prefix`](crate::ConfigLoader::env_prefix)
+    Env {
+        /// The name of the variable
+        name: String,
+    },
+    /// A command-line override, see [`ConfigLoader::overrides`](crate::ConfigLoader::overrides)
+    Cli,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => f.write_str("defaults"),
+            Origin::File {
+                path,
+                line: Some(line),
+            } => write!(f, "{}:{}", path.display(), line),
+            Origin::File { path, line: None } => write!(f, "{}", path.display()),
+            Origin::Env { name } => write!(f, "environment variable {}", name),
+            Origin::Cli => f.write_str("command line"),
+        }
+    }
+}
+
+/// The origin of each value of a configuration, see
+/// [`ConfigLoader::track_origins`](crate::ConfigLoader::track_origins)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Provenance {
+    origins: KeyPathMap<Origin>,
+    values: BTreeMap<String, String>,
+}
+
+impl Provenance {
+    /// Where the value located at @key_path, such as `server.timeout`, comes from. Arrays are
+    /// set as a whole, so `tags[1]` comes from wherever `tags` does.
+    pub fn origin(&self, key_path: &str) -> Option<&Origin> {
+        self.origins.get(key_path)
+    }
+
+    /// The path of each value of the configuration along with its origin, in alphabetical
+    /// order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Origin)> {
+        self.origins.iter()
+    }
+
+    /// Render each value of the configuration along with its origin, one per line, such as
+    /// `server.timeout = 5  # /etc/app/config.toml:12`
+    pub fn explain(&self) -> String {
+        self.values
+            .iter()
+            .map(|(key_path, value)| match self.origin(key_path) {
+                Some(origin) => format!("{} = {}  # {}\n", key_path, value, origin),
+                None => format!("{} = {}\n", key_path, value),
+            })
+            .collect()
+    }
+
+    /// Record that the values of @value, located at @key_path, come from @origin, replacing
+    /// the origin of whatever they override
+    pub(crate) fn record<F>(&mut self, key_path: String, value: &Value, origin: &mut F)
+    where
+        F: FnMut(&str) -> Origin,
+    {
+        self.origins.record(key_path, value, origin);
+    }
+
+    /// Record the origins of @other on top of ours
+    pub(crate) fn merge(&mut self, other: Provenance) {
+        self.origins.merge(other.origins);
+    }
+
+    /// Only keep the origins of the @common top-level keys, and move the ones of the values
+    /// located in each of the top-level @tables, in turn, to the root
+    pub(crate) fn select(&mut self, common: &[&str], tables: &[&str]) {
+        self.origins.select(common, tables);
+    }
+
+    /// Remember the final values of the configuration to explain them, hiding @secrets
+    pub(crate) fn set_values(&mut self, value: &Value, secrets: &[String]) {
+        self.values.clear();
+        self.collect_values(String::new(), value, secrets);
+    }
+
+    fn collect_values(&mut self, key_path: String, value: &Value, secrets: &[String]) {
+        match value {
+            Value::Table(table) if !table.is_empty() => {
+                for (key, value) in table {
+                    self.collect_values(join(&key_path, key), value, secrets);
+                }
+            }
+            _ if key_path.is_empty() => {}
+            Value::String(s) if secrets.contains(s) => {
+                self.values.insert(key_path, "<redacted>".to_string());
+            }
+            value => {
+                self.values
+                    .insert(key_path, redact(&render(value), secrets));
+            }
+        }
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.explain())
+    }
+}
+
+/// Guess the line of @contents where the key located at @key_path is set, looking for each of
+/// its segments in turn
+pub(crate) fn find_line(contents: &str, key_path: &str) -> Option<usize> {
+    let lines = contents.lines().collect::<Vec<_>>();
+    let (mut line, mut column) = (0, 0);
+    for key in key_path.split('.') {
+        loop {
+            let text = lines.get(line)?;
+            if let Some(position) = find_key(&text[column..], key) {
+                column += position + key.len();
+                break;
+            }
+            line += 1;
+            column = 0;
+        }
+    }
+    Some(line + 1)
+}
+
+/// Find @key in @text where it looks like a key in any format: `key =`, `"key":`, `key:`,
+/// `<key>` or `[key]`
+fn find_key(text: &str, key: &str) -> Option<usize> {
+    text.match_indices(key)
+        .map(|(position, _)| position)
+        .find(|position| {
+            let before = text[..*position].chars().next_back();
+            let after = text[position + key.len()..]
+                .trim_start_matches(['"', '\''])
+                .trim_start()
+                .chars()
+                .next();
+            matches!(
+                before,
+                None | Some(' ' | '\t' | '"' | '\'' | '<' | '[' | '.' | '{' | ',')
+            ) && matches!(after, Some('=' | ':' | '>' | '/' | '.' | ']'))
+        })
+}
+
+fn render(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Unsigned(u) => u.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::String(s) => format!("{:?}", s),
+        Value::Datetime(datetime) => datetime.clone(),
+        Value::Array(array) => format!(
+            "[{}]",
+            array.iter().map(render).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Table(table) => format!(
+            "{{{}}}",
+            table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, render(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_line() {
+        let toml = "host = \"answer\"\n[inner]\n# answer\nanswer = 42\n";
+        assert_eq!(find_line(toml, "host"), Some(1));
+        assert_eq!(find_line(toml, "inner.answer"), Some(4));
+        let json = "{\n  \"inner\": {\n    \"answer\": 42\n  }\n}";
+        assert_eq!(find_line(json, "inner.answer"), Some(3));
+        let yaml = "inner:\n  question: answer\n  answer: 42\n";
+        assert_eq!(find_line(yaml, "inner.answer"), Some(3));
+        let xml = "<config>\n  <inner>\n    <answer>42</answer>\n  </inner>\n</config>";
+        assert_eq!(find_line(xml, "inner.answer"), Some(3));
+        assert_eq!(find_line(xml, "inner.question"), None);
+    }
+
+    #[test]
+    fn test_record() {
+        let file = |line| Origin::File {
+            path: PathBuf::from("config.toml"),
+            line: Some(line),
+        };
+        let mut provenance = Provenance::default();
+        let value = Value::Array(vec![Value::Integer(1), Value::Integer(2)]);
+        provenance.record("inner.tags".to_string(), &value, &mut |_| file(1));
+        provenance.record("inner.answer".to_string(), &Value::Integer(41), &mut |_| {
+            file(2)
+        });
+        provenance.record("inner.answer".to_string(), &Value::Integer(42), &mut |_| {
+            Origin::Env {
+                name: "APP_INNER__ANSWER".to_string(),
+            }
+        });
+        assert_eq!(provenance.origin("inner.tags[1]"), Some(&file(1)));
+        assert_eq!(
+            provenance.origin("inner.answer").unwrap().to_string(),
+            "environment variable APP_INNER__ANSWER"
+        );
+        provenance.record("inner".to_string(), &Value::Null, &mut |_| file(3));
+        assert_eq!(provenance.iter().count(), 1);
+        assert_eq!(provenance.origin("inner.answer"), Some(&file(3)));
+        assert_eq!(provenance.origin("other"), None);
+    }
+
+    #[test]
+    fn test_explain_secrets() {
+        let secrets = ["hunter2".to_string(), "multi\nline".to_string()];
+        let mut provenance = Provenance::default();
+        let value = Value::Table(
+            [
+                ("password", Value::String(secrets[1].clone())),
+                ("url", Value::String("db://admin:hunter2@host".to_string())),
+                ("port", Value::Unsigned(u64::MAX)),
+            ]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect(),
+        );
+        provenance.set_values(&value, &secrets);
+        assert_eq!(
+            provenance.explain(),
+            format!(
+                "password = <redacted>\nport = {}\nurl = \"db://admin:<redacted>@host\"\n",
+                u64::MAX
+            )
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let file = |line| Origin::File {
+            path: PathBuf::from("config.toml"),
+            line: Some(line),
+        };
+        let mut provenance = Provenance::default();
+        for (line, key_path) in [
+            "default.host",
+            "default.inner.answer",
+            "staging.host",
+            "production.inner",
+            "name",
+            "other",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            provenance.record(key_path.to_string(), &Value::Null, &mut |_| file(line));
+        }
+        provenance.select(&["name"], &["default", "production"]);
+        assert_eq!(
+            provenance.iter().collect::<Vec<_>>(),
+            vec![("host", &file(0)), ("inner", &file(3)), ("name", &file(4))]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{
+    read_file,
+    value::{self, Value},
+    ConfigFileError, ConfigFormat, ParseError,
+};
+use serde::{
+    de::{DeserializeOwned, Deserializer},
+    Deserialize,
+};
+use std::{collections::HashMap, ffi::OsStr, fmt, path::Path, sync::Arc};
+
+type Parser = Arc<dyn Fn(&str) -> Result<Value, ConfigFileError> + Send + Sync>;
+
+/// A mapping from file extensions to the formats used to parse them.
+///
+/// It starts with the built-in formats, and custom ones can then be registered, possibly
+/// overriding them. Extensions are matched case insensitively.
+///
+/// ```rust,no_run
+/// use config_file::{ConfigFormat, FormatRegistry};
+/// use serde::{
+///     de::{value::Error, IntoDeserializer},
+///     Deserialize,
+/// };
+/// use std::collections::HashMap;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     host: String,
+/// }
+///
+/// # #[cfg(feature = "toml")]
+/// # fn main() {
+/// let registry = FormatRegistry::new()
+///     .register(["cfg"], ConfigFormat::Toml)
+///     .register_parser(["conf"], |contents: &str| {
+///         let entries = contents
+///             .lines()
+///             .filter_map(|line| line.split_once(' '))
+///             .map(|(key, value)| (key.to_string(), value.to_string()))
+///             .collect::<HashMap<_, _>>();
+///         Ok::<_, Error>(entries.into_deserializer())
+///     });
+/// let config: Config = registry.load("/etc/app/app.conf").unwrap();
+/// # }
+/// # #[cfg(not(feature = "toml"))]
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct FormatRegistry {
+    formats: HashMap<String, Entry>,
+}
+
+#[derive(Clone)]
+enum Entry {
+    Builtin(ConfigFormat),
+    Custom(Parser),
+}
+
+impl FormatRegistry {
+    /// Create a registry knowing about all the enabled built-in formats
+    pub fn new() -> Self {
+        ConfigFormat::ALL
+            .iter()
+            .fold(Self::empty(), |registry, format| {
+                registry.register(format.extensions().iter().copied(), *format)
+            })
+    }
+
+    /// Create a registry without any format
+    pub fn empty() -> Self {
+        Self {
+            formats: HashMap::new(),
+        }
+    }
+
+    /// Parse files with one of @extensions using the built-in @format
+    pub fn register<I, S>(self, extensions: I, format: ConfigFormat) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.insert(extensions, Entry::Builtin(format))
+    }
+
+    /// Parse files with one of @extensions using @parser.
+    ///
+    /// The parser returns a serde deserializer for the contents of the file, such as a
+    /// `serde_json::Value` or a map turned into one with `IntoDeserializer`, which will then be
+    /// deserialized into the requested type. Its values must already have the expected types,
+    /// strings aren't converted to numbers or booleans like they are for XML.
+    pub fn register_parser<I, S, F, D>(self, extensions: I, parser: F) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+        F: Fn(&str) -> Result<D, D::Error> + Send + Sync + 'static,
+        D: Deserializer<'static>,
+        D::Error: Send + Sync + 'static,
+    {
+        let parser = move |contents: &str| {
+            parser(contents)
+                .and_then(Value::deserialize)
+                .map_err(|err| {
+                    ParseError::new(ConfigFileError::Custom(Box::new(err)), contents).into()
+                })
+        };
+        self.insert(extensions, Entry::Custom(Arc::new(parser)))
+    }
+
+    fn insert<I, S>(mut self, extensions: I, entry: Entry) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for extension in extensions {
+            self.formats
+                .insert(extension.as_ref().to_lowercase(), entry.clone());
+        }
+        self
+    }
+
+    /// Whether we know how to parse the file located at @path
+    pub fn supports<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.entry(path.as_ref()).is_some()
+    }
+
+    /// Load the configuration file located at @path with the format registered for its extension
+    pub fn load<C: DeserializeOwned, P: AsRef<Path>>(&self, path: P) -> Result<C, ConfigFileError> {
+        let path = path.as_ref();
+        let entry = self.entry(path).ok_or(ConfigFileError::UnsupportedFormat)?;
+        let contents = read_file(path)?;
+        match entry {
+            Entry::Builtin(format) => format.deserialize(&contents),
+            Entry::Custom(parser) => {
+                parser(&contents).and_then(|value| Ok(value::from_value(value, &|_| false)?))
+            }
+        }
+        .map_err(|err| err.with_path(path))
+    }
+
+    /// Whether the format of the file located at @path only has strings, which must then be
+    /// converted to the expected types
+    pub(crate) fn is_untyped(&self, path: &Path) -> bool {
+        match self.entry(path) {
+            Some(Entry::Builtin(format)) => format.is_untyped(),
+            _ => false,
+        }
+    }
+
+    fn entry(&self, path: &Path) -> Option<&Entry> {
+        let extension = path.extension().and_then(OsStr::to_str)?;
+        self.formats.get(&extension.to_lowercase())
+    }
+}
+
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for FormatRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut extensions = self.formats.keys().collect::<Vec<_>>();
+        extensions.sort();
+        f.debug_struct("FormatRegistry")
+            .field("extensions", &extensions)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::de::{self, value::MapDeserializer, IntoDeserializer};
+    use std::collections::{btree_map, BTreeMap};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct TestConfig {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct ConfConfig {
+        host: String,
+        port: String,
+    }
+
+    type ConfDeserializer =
+        MapDeserializer<'static, btree_map::IntoIter<String, String>, de::value::Error>;
+
+    fn parse_conf(contents: &str) -> Result<ConfDeserializer, de::value::Error> {
+        contents
+            .lines()
+            .map(|line| match line.split_once(' ') {
+                Some((key, value)) => Ok((key.to_string(), value.to_string())),
+                None => Err(de::Error::custom(format!("invalid line: {}", line))),
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()
+            .map(IntoDeserializer::into_deserializer)
+    }
+
+    #[test]
+    fn test_custom() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.CONF");
+        std::fs::write(&path, "host example.com\nport 443").unwrap();
+        let registry = FormatRegistry::new().register_parser(["conf"], parse_conf);
+        assert_eq!(
+            registry.load::<ConfConfig, _>(&path).unwrap(),
+            ConfConfig {
+                host: "example.com".to_string(),
+                port: "443".to_string(),
+            }
+        );
+        // Custom formats aren't untyped like XML
+        let res = registry.load::<TestConfig, _>(&path);
+        assert!(matches!(res, Err(ConfigFileError::Value(_))));
+        std::fs::write(&path, "host=example.com").unwrap();
+        match registry.load::<TestConfig, _>(&path) {
+            Err(ConfigFileError::Parse(err)) => {
+                assert!(matches!(err.error(), ConfigFileError::Custom(_)));
+                assert_eq!(err.path(), Some(path.as_path()));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.cfg");
+        std::fs::write(&path, "host = \"example.com\"\nport = 443").unwrap();
+        assert!(matches!(
+            FormatRegistry::new().load::<TestConfig, _>(&path),
+            Err(ConfigFileError::UnsupportedFormat)
+        ));
+        let registry = FormatRegistry::new().register(["cfg"], ConfigFormat::Toml);
+        assert!(registry.supports(&path));
+        assert_eq!(registry.load::<TestConfig, _>(&path).unwrap().port, 443);
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_override_builtin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.toml");
+        std::fs::write(&path, "host example.com\nport 443").unwrap();
+        let registry = FormatRegistry::new().register_parser(["toml"], parse_conf);
+        assert_eq!(registry.load::<ConfConfig, _>(&path).unwrap().port, "443");
+        assert!(!FormatRegistry::empty().supports(&path));
+    }
+}
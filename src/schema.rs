@@ -0,0 +1,585 @@
+use crate::{key_path::join, value::Value, Violation};
+use schemars::{
+    schema::{
+        ArrayValidation, InstanceType, ObjectValidation, RootSchema, Schema, SchemaObject,
+        SingleOrVec,
+    },
+    JsonSchema,
+};
+use serde_json::Value as Json;
+use std::collections::BTreeMap;
+
+/// Generate the JSON Schema of the configuration type @C, which editors can use to provide
+/// completion and validation.
+///
+/// ```rust
+/// use schemars::JsonSchema;
+///
+/// #[derive(JsonSchema)]
+/// struct Config {
+///     host: String,
+/// }
+///
+/// let schema = config_file::json_schema::<Config>();
+/// println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+/// ```
+pub fn json_schema<C: JsonSchema>() -> RootSchema {
+    schemars::schema_for!(C)
+}
+
+/// Check @value against @schema, returning every violation along with the path of the key which
+/// caused it.
+///
+/// The values for whose key path @untyped returns true come from formats which only have
+/// strings, such as XML, and are checked like they are deserialized: strings are accepted where
+/// booleans or numbers are expected if they can be parsed as such, and single values where
+/// arrays are expected.
+///
+/// The `pattern`, `format` and `patternProperties` keywords aren't checked, since they would
+/// require regular expressions and parsers for every format, and the keys which don't have a
+/// property of their own aren't checked against `additionalProperties` when `patternProperties`
+/// is set.
+pub(crate) fn validate(
+    schema: &RootSchema,
+    value: &Value,
+    untyped: &dyn Fn(&str) -> bool,
+) -> Result<(), Vec<Violation>> {
+    let validator = Validator {
+        root: schema,
+        untyped,
+    };
+    let violations = validator.check_object(&schema.schema, value, "");
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+struct Validator<'a> {
+    root: &'a RootSchema,
+    untyped: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&self, schema: &Schema, value: &Value, key_path: &str) -> Vec<Violation> {
+        match schema {
+            Schema::Bool(true) => Vec::new(),
+            Schema::Bool(false) => vec![violation(key_path, "is not allowed".to_string())],
+            Schema::Object(schema) => self.check_object(schema, value, key_path),
+        }
+    }
+
+    fn matches(&self, schema: &Schema, value: &Value, key_path: &str) -> bool {
+        self.check(schema, value, key_path).is_empty()
+    }
+
+    fn check_object(&self, schema: &SchemaObject, value: &Value, key_path: &str) -> Vec<Violation> {
+        let untyped = (self.untyped)(key_path);
+        let mut violations = Vec::new();
+        if let Some(reference) = &schema.reference {
+            match reference
+                .strip_prefix("#/definitions/")
+                .and_then(|name| self.root.definitions.get(name))
+            {
+                Some(schema) => violations.extend(self.check(schema, value, key_path)),
+                None => violations.push(violation(
+                    key_path,
+                    format!("unknown schema reference {}", reference),
+                )),
+            }
+        }
+        if let Some(types) = &schema.instance_type {
+            let types: &[InstanceType] = match types {
+                SingleOrVec::Single(instance_type) => std::slice::from_ref(instance_type),
+                SingleOrVec::Vec(types) => types,
+            };
+            if !types.iter().any(|t| has_type(value, *t, untyped)) {
+                let expected = types
+                    .iter()
+                    .map(|t| type_name(*t))
+                    .collect::<Vec<_>>()
+                    .join(" or ");
+                violations.push(violation(
+                    key_path,
+                    format!("expected {}, found {}", expected, kind(value)),
+                ));
+                return violations;
+            }
+        }
+        if let Some(values) = &schema.enum_values {
+            if !values.iter().any(|json| equals(value, json, untyped)) {
+                let values = values
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                violations.push(violation(key_path, format!("must be one of {}", values)));
+            }
+        }
+        if let Some(json) = &schema.const_value {
+            if !equals(value, json, untyped) {
+                violations.push(violation(key_path, format!("must be {}", json)));
+            }
+        }
+        if let Some(subschemas) = &schema.subschemas {
+            for schema in subschemas.all_of.iter().flatten() {
+                violations.extend(self.check(schema, value, key_path));
+            }
+            if let Some(alternatives) = &subschemas.any_of {
+                if !alternatives
+                    .iter()
+                    .any(|schema| self.matches(schema, value, key_path))
+                {
+                    violations.push(violation(
+                        key_path,
+                        "doesn't match any of the allowed variants".to_string(),
+                    ));
+                }
+            }
+            if let Some(alternatives) = &subschemas.one_of {
+                let matching = alternatives
+                    .iter()
+                    .filter(|schema| self.matches(schema, value, key_path))
+                    .count();
+                match matching {
+                    1 => {}
+                    0 => violations.push(violation(
+                        key_path,
+                        "doesn't match any of the allowed variants".to_string(),
+                    )),
+                    _ => violations.push(violation(
+                        key_path,
+                        "matches more than one of the allowed variants".to_string(),
+                    )),
+                }
+            }
+            if let Some(schema) = &subschemas.not {
+                if self.matches(schema, value, key_path) {
+                    violations.push(violation(
+                        key_path,
+                        "matches a schema it must not match".to_string(),
+                    ));
+                }
+            }
+            if let Some(condition) = &subschemas.if_schema {
+                let branch = if self.matches(condition, value, key_path) {
+                    &subschemas.then_schema
+                } else {
+                    &subschemas.else_schema
+                };
+                if let Some(schema) = branch {
+                    violations.extend(self.check(schema, value, key_path));
+                }
+            }
+        }
+        if let (Some(number), Some(n)) = (&schema.number, as_number(value, untyped)) {
+            let bounds = [
+                (number.minimum, n >= number.minimum.unwrap_or(n), "at least"),
+                (number.maximum, n <= number.maximum.unwrap_or(n), "at most"),
+                (
+                    number.exclusive_minimum,
+                    n > number.exclusive_minimum.unwrap_or(f64::NEG_INFINITY),
+                    "greater than",
+                ),
+                (
+                    number.exclusive_maximum,
+                    n < number.exclusive_maximum.unwrap_or(f64::INFINITY),
+                    "less than",
+                ),
+            ];
+            for (bound, ok, relation) in bounds {
+                if let (Some(bound), false) = (bound, ok) {
+                    violations.push(violation(
+                        key_path,
+                        format!("must be {} {}", relation, bound),
+                    ));
+                }
+            }
+            if let Some(multiple) = number.multiple_of.filter(|multiple| *multiple > 0.0) {
+                let quotient = n / multiple;
+                if (quotient - quotient.round()).abs() > 1e-9 {
+                    violations.push(violation(
+                        key_path,
+                        format!("must be a multiple of {}", multiple),
+                    ));
+                }
+            }
+        }
+        if let (Some(string), Value::String(s)) = (&schema.string, value) {
+            let len = s.chars().count() as u32;
+            if let Some(min) = string.min_length.filter(|min| len < *min) {
+                violations.push(violation(
+                    key_path,
+                    format!("must be at least {} characters long", min),
+                ));
+            }
+            if let Some(max) = string.max_length.filter(|max| len > *max) {
+                violations.push(violation(
+                    key_path,
+                    format!("must be at most {} characters long", max),
+                ));
+            }
+        }
+        if let Some(array) = &schema.array {
+            let items = match value {
+                Value::Array(items) => items.as_slice(),
+                // Like when deserializing, a single value is a list of one element
+                Value::Null if untyped => &[],
+                value if untyped => std::slice::from_ref(value),
+                _ => &[],
+            };
+            violations.extend(self.check_array(array, items, key_path));
+        }
+        if let (Some(object), Value::Table(table)) = (&schema.object, value) {
+            violations.extend(self.check_table(object, table, key_path));
+        }
+        violations
+    }
+
+    fn check_array(
+        &self,
+        array: &ArrayValidation,
+        items: &[Value],
+        key_path: &str,
+    ) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let len = items.len() as u32;
+        if let Some(min) = array.min_items.filter(|min| len < *min) {
+            violations.push(violation(
+                key_path,
+                format!("must have at least {} items", min),
+            ));
+        }
+        if let Some(max) = array.max_items.filter(|max| len > *max) {
+            violations.push(violation(
+                key_path,
+                format!("must have at most {} items", max),
+            ));
+        }
+        if array.unique_items == Some(true) {
+            let duplicate = items
+                .iter()
+                .enumerate()
+                .any(|(index, item)| items[..index].contains(item));
+            if duplicate {
+                violations.push(violation(
+                    key_path,
+                    "must not have duplicate items".to_string(),
+                ));
+            }
+        }
+        let item_path = |index: usize| join(key_path, &format!("[{}]", index));
+        if let Some(schema) = &array.contains {
+            if !items
+                .iter()
+                .enumerate()
+                .any(|(index, item)| self.matches(schema, item, &item_path(index)))
+            {
+                violations.push(violation(
+                    key_path,
+                    "doesn't have any item matching the expected schema".to_string(),
+                ));
+            }
+        }
+        for (index, item) in items.iter().enumerate() {
+            let schema = match &array.items {
+                Some(SingleOrVec::Single(schema)) => Some(&**schema),
+                Some(SingleOrVec::Vec(schemas)) => {
+                    schemas.get(index).or(array.additional_items.as_deref())
+                }
+                None => None,
+            };
+            if let Some(schema) = schema {
+                violations.extend(self.check(schema, item, &item_path(index)));
+            }
+        }
+        violations
+    }
+
+    fn check_table(
+        &self,
+        object: &ObjectValidation,
+        table: &BTreeMap<String, Value>,
+        key_path: &str,
+    ) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for key in &object.required {
+            if !table.contains_key(key) {
+                violations.push(violation(&join(key_path, key), "is missing".to_string()));
+            }
+        }
+        let len = table.len() as u32;
+        if let Some(min) = object.min_properties.filter(|min| len < *min) {
+            violations.push(violation(
+                key_path,
+                format!("must have at least {} keys", min),
+            ));
+        }
+        if let Some(max) = object.max_properties.filter(|max| len > *max) {
+            violations.push(violation(
+                key_path,
+                format!("must have at most {} keys", max),
+            ));
+        }
+        for (key, value) in table {
+            let key_path = join(key_path, key);
+            if let Some(schema) = &object.property_names {
+                let name = Value::String(key.clone());
+                if !self.matches(schema, &name, &key_path) {
+                    violations.push(violation(&key_path, "is not an allowed key".to_string()));
+                }
+            }
+            let additional = match object.additional_properties.as_deref() {
+                // We can't tell which keys patternProperties matches
+                Some(_) if !object.pattern_properties.is_empty() => None,
+                additional => additional,
+            };
+            if let Some(schema) = object.properties.get(key).or(additional) {
+                violations.extend(self.check(schema, value, &key_path));
+            }
+        }
+        violations
+    }
+}
+
+fn violation(key_path: &str, message: String) -> Violation {
+    Violation::new(
+        Some(key_path.to_string()).filter(|key_path| !key_path.is_empty()),
+        message,
+    )
+}
+
+/// Whether @value has the type @instance_type, @untyped values being parsed from strings
+fn has_type(value: &Value, instance_type: InstanceType, untyped: bool) -> bool {
+    match (instance_type, value) {
+        (InstanceType::Null, Value::Null)
+        | (InstanceType::Boolean, Value::Bool(_))
+        | (InstanceType::Integer, Value::Integer(_) | Value::Unsigned(_))
+        | (InstanceType::Number, Value::Integer(_) | Value::Unsigned(_) | Value::Float(_))
+        | (InstanceType::String, Value::String(_) | Value::Datetime(_))
+        | (InstanceType::Object, Value::Table(_))
+        | (InstanceType::Array, Value::Array(_)) => true,
+        (InstanceType::Integer, Value::Float(f)) => f.fract() == 0.0,
+        // Formats such as XML only have strings, and can't tell single values from lists
+        (InstanceType::Array, _) => untyped,
+        (InstanceType::Boolean, Value::String(s)) => untyped && s.parse::<bool>().is_ok(),
+        (InstanceType::Integer, Value::String(s)) => {
+            untyped && (s.parse::<i64>().is_ok() || s.parse::<u64>().is_ok())
+        }
+        (InstanceType::Number, Value::String(s)) => untyped && s.parse::<f64>().is_ok(),
+        _ => false,
+    }
+}
+
+fn type_name(instance_type: InstanceType) -> &'static str {
+    match instance_type {
+        InstanceType::Null => "null",
+        InstanceType::Boolean => "boolean",
+        InstanceType::Object => "table",
+        InstanceType::Array => "array",
+        InstanceType::Number => "number",
+        InstanceType::String => "string",
+        InstanceType::Integer => "integer",
+    }
+}
+
+fn kind(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => format!("boolean {}", b),
+        Value::Integer(i) => format!("integer {}", i),
+        Value::Unsigned(u) => format!("integer {}", u),
+        Value::Float(f) => format!("number {}", f),
+        Value::String(s) => format!("string {:?}", s),
+        Value::Datetime(datetime) => format!("datetime {}", datetime),
+        Value::Array(_) => "array".to_string(),
+        Value::Table(_) => "table".to_string(),
+    }
+}
+
+fn as_number(value: &Value, untyped: bool) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Unsigned(u) => Some(*u as f64),
+        Value::Float(f) => Some(*f),
+        Value::String(s) if untyped => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn equals(value: &Value, json: &Json, untyped: bool) -> bool {
+    match (value, json) {
+        (Value::Null, Json::Null) => true,
+        (Value::Bool(b), Json::Bool(other)) => b == other,
+        (Value::String(s) | Value::Datetime(s), Json::String(other)) => s == other,
+        (Value::String(s), json @ (Json::Bool(_) | Json::Number(_))) if untyped => {
+            s == &json.to_string()
+        }
+        (value, Json::Number(n)) => as_number(value, untyped) == n.as_f64(),
+        (Value::Array(items), Json::Array(other)) => {
+            items.len() == other.len()
+                && items.iter().zip(other).all(|(a, b)| equals(a, b, untyped))
+        }
+        (Value::Table(table), Json::Object(other)) => {
+            table.len() == other.len()
+                && table.iter().all(|(key, value)| {
+                    other
+                        .get(key)
+                        .map_or(false, |json| equals(value, json, untyped))
+                })
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[allow(unused)]
+    #[derive(Deserialize, JsonSchema)]
+    #[serde(rename_all = "lowercase")]
+    enum Mode {
+        Plain,
+        Tls { cert: String },
+    }
+
+    #[allow(unused)]
+    #[derive(Deserialize, JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct Server {
+        host: String,
+        port: u16,
+        mode: Mode,
+        tags: Vec<String>,
+        weight: Option<f64>,
+    }
+
+    fn validate_json(schema: &RootSchema, contents: &str, untyped: bool) -> Vec<String> {
+        let value: Value = serde_json::from_str(contents).unwrap();
+        match validate(schema, &value, &|_| untyped) {
+            Ok(()) => Vec::new(),
+            Err(violations) => violations.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn check(contents: &str) -> Vec<String> {
+        validate_json(&json_schema::<Server>(), contents, false)
+    }
+
+    fn schema(json: Json) -> RootSchema {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_valid() {
+        assert!(check(r#"{ "host": "a", "port": 443, "mode": "plain", "tags": [] }"#).is_empty());
+        let untyped = r#"{ "host": "a", "port": "443", "mode": { "tls": { "cert": "c" } }, "tags": "t", "weight": 0.5 }"#;
+        assert!(validate_json(&json_schema::<Server>(), untyped, true).is_empty());
+        assert_eq!(
+            check(untyped),
+            vec![
+                "port: expected integer, found string \"443\"",
+                "tags: expected array, found string \"t\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_violations() {
+        assert_eq!(
+            check(
+                r#"{ "host": 1, "port": -1, "mode": "ssl", "tags": [1], "weight": null, "extra": true }"#
+            ),
+            vec![
+                "extra: is not allowed",
+                "host: expected string, found integer 1",
+                "mode: doesn't match any of the allowed variants",
+                "port: must be at least 0",
+                "tags[0]: expected string, found integer 1",
+            ]
+        );
+        assert_eq!(
+            check(r#"{ "host": "a", "mode": "plain", "tags": [] }"#),
+            vec!["port: is missing"]
+        );
+    }
+
+    #[test]
+    fn test_combinators() {
+        let one_of = schema(serde_json::json!({
+            "oneOf": [{ "type": "integer" }, { "type": "number", "minimum": 0 }]
+        }));
+        assert!(validate_json(&one_of, "-1", false).is_empty());
+        assert_eq!(
+            validate_json(&one_of, "1", false),
+            vec!["matches more than one of the allowed variants"]
+        );
+        assert_eq!(
+            validate_json(&one_of, "-0.5", false),
+            vec!["doesn't match any of the allowed variants"]
+        );
+
+        let not = schema(serde_json::json!({ "not": { "type": "string" } }));
+        assert!(validate_json(&not, "1", false).is_empty());
+        assert_eq!(
+            validate_json(&not, r#""a""#, false),
+            vec!["matches a schema it must not match"]
+        );
+
+        let condition = schema(serde_json::json!({
+            "if": { "properties": { "tls": { "const": true } } },
+            "then": { "required": ["cert"] },
+            "else": { "properties": { "cert": false } }
+        }));
+        assert!(validate_json(&condition, r#"{ "tls": true, "cert": "c" }"#, false).is_empty());
+        assert!(validate_json(&condition, r#"{ "tls": false }"#, false).is_empty());
+        assert_eq!(
+            validate_json(&condition, r#"{ "tls": true }"#, false),
+            vec!["cert: is missing"]
+        );
+        assert_eq!(
+            validate_json(&condition, r#"{ "tls": false, "cert": "c" }"#, false),
+            vec!["cert: is not allowed"]
+        );
+    }
+
+    #[test]
+    fn test_keywords() {
+        let array = schema(serde_json::json!({
+            "type": "array",
+            "items": { "type": "integer", "multipleOf": 2 },
+            "uniqueItems": true,
+            "contains": { "type": "integer", "minimum": 10 }
+        }));
+        assert!(validate_json(&array, "[2, 10]", false).is_empty());
+        assert_eq!(
+            validate_json(&array, r#"[2, 2, "4", 3]"#, false),
+            vec![
+                "must not have duplicate items",
+                "doesn't have any item matching the expected schema",
+                "[2]: expected integer, found string \"4\"",
+                "[3]: must be a multiple of 2",
+            ]
+        );
+
+        let table = schema(serde_json::json!({
+            "type": "object",
+            "minProperties": 1,
+            "maxProperties": 2,
+            "propertyNames": { "maxLength": 3 }
+        }));
+        assert!(validate_json(&table, r#"{ "a": 1 }"#, false).is_empty());
+        assert_eq!(
+            validate_json(&table, "{}", false),
+            vec!["must have at least 1 keys"]
+        );
+        assert_eq!(
+            validate_json(&table, r#"{ "a": 1, "b": 2, "long": 3 }"#, false),
+            vec!["must have at most 2 keys", "long: is not an allowed key"]
+        );
+    }
+}
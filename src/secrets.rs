@@ -0,0 +1,184 @@
+use crate::{
+    key_path::{is_within, join, KeyPathMap},
+    value::Value,
+    ConfigFileError,
+};
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+/// Replace the values of the keys located at @secret_keys with the contents of the files they
+/// point to, returning these contents so that they can be redacted.
+///
+/// Such a key is read from a file when its value is a `file:` URI, or when it is set with a
+/// `_file` suffix instead. The secrets are plain strings, so they are flagged in @untyped.
+pub(crate) fn resolve(
+    value: &mut Value,
+    secret_keys: &[String],
+    untyped: &mut KeyPathMap<bool>,
+) -> Result<Vec<String>, ConfigFileError> {
+    let mut resolver = Resolver {
+        secret_keys,
+        untyped,
+        secrets: Vec::new(),
+    };
+    resolver.resolve(value, String::new())?;
+    Ok(resolver.secrets)
+}
+
+struct Resolver<'a> {
+    secret_keys: &'a [String],
+    untyped: &'a mut KeyPathMap<bool>,
+    secrets: Vec<String>,
+}
+
+impl Resolver<'_> {
+    /// Whether the value located at @key_path is a secret, or is inside one such as `tokens[0]`
+    fn is_secret(&self, key_path: &str) -> bool {
+        self.secret_keys
+            .iter()
+            .any(|key| key == key_path || is_within(key_path, key))
+    }
+
+    fn resolve(&mut self, value: &mut Value, key_path: String) -> Result<(), ConfigFileError> {
+        match value {
+            Value::String(s) if self.is_secret(&key_path) => {
+                if let Some(path) = s.strip_prefix("file:") {
+                    // Accept file:///run/secrets/db as well as file:/run/secrets/db
+                    let path = path.strip_prefix("//").unwrap_or(path).to_string();
+                    *value = Value::String(self.read(&key_path, path)?);
+                    self.untyped.insert(key_path, true);
+                }
+            }
+            Value::Array(array) => {
+                for (index, value) in array.iter_mut().enumerate() {
+                    self.resolve(value, join(&key_path, &format!("[{}]", index)))?;
+                }
+            }
+            Value::Table(table) => {
+                let mut resolved = BTreeMap::new();
+                for (key, value) in table.iter_mut() {
+                    let path = join(&key_path, key);
+                    let name = key
+                        .strip_suffix("_file")
+                        .filter(|name| self.is_secret(&join(&key_path, name)));
+                    match (name, &*value) {
+                        (Some(name), Value::String(file)) => {
+                            let secret = self.read(&path, file.clone())?;
+                            resolved.insert(key.clone(), (name.to_string(), secret));
+                        }
+                        _ => self.resolve(value, path)?,
+                    }
+                }
+                for (key, (name, secret)) in resolved {
+                    table.remove(&key);
+                    self.untyped.insert(join(&key_path, &name), true);
+                    table.insert(name, Value::String(secret));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, key_path: &str, path: String) -> Result<String, ConfigFileError> {
+        let path = PathBuf::from(path);
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let secret = contents.trim().to_string();
+                if !secret.is_empty() {
+                    self.secrets.push(secret.clone());
+                }
+                Ok(secret)
+            }
+            Err(source) => Err(ConfigFileError::Secret {
+                key_path: key_path.to_string(),
+                path,
+                source,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("db");
+        let api = dir.path().join("api");
+        fs::write(&db, "hunter2\n").unwrap();
+        fs::write(&api, "s3cr3t").unwrap();
+        let mut value = Value::Table(
+            [
+                ("password_file", Value::String(db.display().to_string())),
+                (
+                    "tokens",
+                    Value::Array(vec![Value::String(format!("file://{}", api.display()))]),
+                ),
+                ("user", Value::String("admin".to_string())),
+                ("log_file", Value::String("/var/log/app.log".to_string())),
+                ("motd", Value::String("file:/etc/motd".to_string())),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+        );
+        let mut untyped = KeyPathMap::default();
+        let secret_keys = ["password".to_string(), "tokens".to_string()];
+        assert_eq!(
+            resolve(&mut value, &secret_keys, &mut untyped).unwrap(),
+            vec!["hunter2", "s3cr3t"]
+        );
+        assert!(untyped.is_flagged("password"));
+        assert!(untyped.is_flagged("tokens[0]"));
+        assert_eq!(
+            value,
+            Value::Table(
+                [
+                    ("password", Value::String("hunter2".to_string())),
+                    ("log_file", Value::String("/var/log/app.log".to_string())),
+                    ("motd", Value::String("file:/etc/motd".to_string())),
+                    (
+                        "tokens",
+                        Value::Array(vec![Value::String("s3cr3t".to_string())])
+                    ),
+                    ("user", Value::String("admin".to_string())),
+                ]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_unreadable() {
+        let mut value = Value::Table(
+            [(
+                "db".to_string(),
+                Value::Table(
+                    [(
+                        "password_file".to_string(),
+                        Value::String("/run/secrets/missing".to_string()),
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let err = resolve(
+            &mut value,
+            &["db.password".to_string()],
+            &mut KeyPathMap::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.key_path(), Some("db.password_file"));
+        assert_eq!(
+            err.to_string(),
+            "couldn't read secret /run/secrets/missing for db.password_file"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::key_path::{join, redact};
+use std::fmt;
+
+/// Trait for checking the constraints serde can't express once a configuration is deserialized,
+/// such as ranges or fields which depend on each other.
+///
+/// Every violation is reported, instead of stopping at the first one, so that they can all be
+/// fixed at once.
+///
+/// ```rust
+/// use config_file::{Validate, Violations};
+///
+/// struct Config {
+///     port: u16,
+///     cert: Option<String>,
+///     acme: bool,
+/// }
+///
+/// impl Validate for Config {
+///     fn validate(&self, violations: &mut Violations) {
+///         if self.port == 0 {
+///             violations.add("port", "must be in 1..=65535");
+///         }
+///         if self.cert.is_none() && !self.acme {
+///             violations.add("", "either cert or acme must be set");
+///         }
+///     }
+/// }
+/// ```
+pub trait Validate {
+    /// Report everything which is wrong with ourselves to @violations
+    fn validate(&self, violations: &mut Violations);
+}
+
+/// The violations reported by [`Validate::validate`]
+#[derive(Debug, Default)]
+pub struct Violations {
+    prefix: String,
+    violations: Vec<Violation>,
+}
+
+impl Violations {
+    /// Report that the value located at @key_path, such as `server.port`, is invalid because of
+    /// @message. An empty @key_path refers to the whole configuration.
+    pub fn add<M: fmt::Display>(&mut self, key_path: &str, message: M) {
+        let key_path = join(&self.prefix, key_path);
+        self.violations.push(Violation::new(
+            Some(key_path).filter(|key_path| !key_path.is_empty()),
+            message.to_string(),
+        ));
+    }
+
+    /// Validate @value, located at @key_path, reporting its violations relatively to it
+    pub fn nested<V: Validate + ?Sized>(&mut self, key_path: &str, value: &V) {
+        let prefix = join(&self.prefix, key_path);
+        let parent = std::mem::replace(&mut self.prefix, prefix);
+        value.validate(self);
+        self.prefix = parent;
+    }
+
+    /// Whether no violation was reported
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub(crate) fn check<V: Validate + ?Sized>(value: &V) -> Result<(), Vec<Violation>> {
+        let mut violations = Self::default();
+        value.validate(&mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations.violations)
+        }
+    }
+}
+
+/// A constraint which a configuration doesn't satisfy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    key_path: Option<String>,
+    message: String,
+}
+
+impl Violation {
+    pub(crate) fn new(key_path: Option<String>, message: String) -> Self {
+        Self { key_path, message }
+    }
+
+    /// Hide @secrets from the message, which can quote the offending value
+    pub(crate) fn redact(mut self, secrets: &[String]) -> Self {
+        self.message = redact(&self.message, secrets);
+        self
+    }
+
+    /// The path of the invalid key, such as `server.port`, or `None` if the violation is about
+    /// the whole configuration
+    pub fn key_path(&self) -> Option<&str> {
+        self.key_path.as_deref()
+    }
+
+    /// What is wrong
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(key_path) = &self.key_path {
+            write!(f, "{}: ", key_path)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Server {
+        port: u16,
+    }
+
+    impl Validate for Server {
+        fn validate(&self, violations: &mut Violations) {
+            if self.port == 0 {
+                violations.add("port", "must be in 1..=65535");
+            }
+        }
+    }
+
+    struct Config {
+        servers: Vec<Server>,
+        cert: Option<String>,
+        acme: bool,
+    }
+
+    impl Validate for Config {
+        fn validate(&self, violations: &mut Violations) {
+            for (index, server) in self.servers.iter().enumerate() {
+                violations.nested(&format!("servers[{}]", index), server);
+            }
+            if self.cert.is_none() && !self.acme {
+                violations.add("", "either cert or acme must be set");
+            }
+        }
+    }
+
+    #[test]
+    fn test_violations() {
+        let config = Config {
+            servers: vec![Server { port: 0 }, Server { port: 443 }, Server { port: 0 }],
+            cert: None,
+            acme: false,
+        };
+        let violations = Violations::check(&config).unwrap_err();
+        assert_eq!(
+            violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "servers[0].port: must be in 1..=65535",
+                "servers[2].port: must be in 1..=65535",
+                "either cert or acme must be set",
+            ]
+        );
+        assert_eq!(violations[2].key_path(), None);
+        assert!(Violations::check(&Server { port: 443 }).is_ok());
+    }
+}
@@ -0,0 +1,1214 @@
+//! Format agnostic representation of a configuration document.
+//!
+//! Every supported format can be deserialized into a [`Value`], which can then be merged with
+//! other documents and finally deserialized into the user's type.
+
+use crate::{
+    error::deserialize_tracked,
+    key_path::{join, redact},
+    ConfigFileError, ConfigFormat,
+};
+use serde::{
+    de::{
+        self, value::StringDeserializer, DeserializeOwned, DeserializeSeed, Deserializer,
+        EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize, Serialize,
+};
+use std::{cell::Cell, collections::BTreeMap, fmt, thread::LocalKey};
+
+mod interpolate;
+mod ser;
+
+pub(crate) use interpolate::interpolate;
+pub(crate) use ser::to_value;
+
+/// The way TOML datetimes go through serde: a struct with this name and a single field
+pub(crate) const TOML_DATETIME_NAME: &str = "$__toml_private_Datetime";
+pub(crate) const TOML_DATETIME_FIELD: &str = "$__toml_private_datetime";
+
+/// A configuration document, independent of the format it was read from, for inspecting
+/// configurations without a concrete type.
+///
+/// Any supported format can be loaded into it, with [`FromConfigFile`](crate::FromConfigFile)
+/// or [`ConfigLoader`](crate::ConfigLoader), and it can be written back to any of them with
+/// [`ToConfigFile`](crate::ToConfigFile) or [`ConfigValue::to_config_string`](Value::to_config_string).
+///
+/// ```rust,no_run
+/// use config_file::{ConfigValue, FromConfigFile};
+///
+/// let mut value = ConfigValue::from_config_file("/etc/app/config.toml").unwrap();
+/// if value.get("inner.answer") != Some(&ConfigValue::Integer(42)) {
+///     value.set("inner.answer", ConfigValue::Integer(42)).unwrap();
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A missing value, such as JSON's `null`
+    Null,
+    /// A boolean
+    Bool(bool),
+    /// An integer
+    Integer(i64),
+    /// An integer above `i64::MAX`, the other ones being [`ConfigValue::Integer`](Value::Integer)
+    Unsigned(u64),
+    /// A floating point number
+    Float(f64),
+    /// A string
+    String(String),
+    /// A TOML datetime, such as `1979-05-27T07:32:00Z`, kept as written. Other formats don't
+    /// have datetimes and use strings instead, which is also how they are written.
+    Datetime(String),
+    /// A list of values
+    Array(Vec<Value>),
+    /// Values indexed by their key, in alphabetical order
+    Table(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// The value located at @key_path, such as `inner.answer` or `servers[0].host`
+    pub fn get(&self, key_path: &str) -> Option<&Value> {
+        let mut value = self;
+        for segment in parse_key_path(key_path)? {
+            value = match (value, segment) {
+                (Value::Table(table), Segment::Key(key)) => table.get(key),
+                (Value::Array(array), Segment::Index(index)) => array.get(index),
+                _ => None,
+            }?;
+        }
+        Some(value)
+    }
+
+    /// The value located at @key_path like [`ConfigValue::get`](Value::get), which can be modified
+    pub fn get_mut(&mut self, key_path: &str) -> Option<&mut Value> {
+        let mut value = self;
+        for segment in parse_key_path(key_path)? {
+            value = match (value, segment) {
+                (Value::Table(table), Segment::Key(key)) => table.get_mut(key),
+                (Value::Array(array), Segment::Index(index)) => array.get_mut(index),
+                _ => None,
+            }?;
+        }
+        Some(value)
+    }
+
+    /// Set the value located at @key_path, such as `inner.answer` or `servers[0].host`, to
+    /// @value, creating the intermediate tables as needed. Array elements are replaced in place,
+    /// or appended when their index is the length of the array.
+    pub fn set(&mut self, key_path: &str, value: Value) -> Result<(), ValueError> {
+        let error = |message: String| ValueError {
+            message,
+            key_path: Some(key_path.to_string()),
+        };
+        let segments = parse_key_path(key_path)
+            .filter(|segments| !segments.is_empty())
+            .ok_or_else(|| error("invalid key path".to_string()))?;
+        self.set_segments(&segments, value).map_err(error)
+    }
+
+    /// Convert the configuration of type @C into a document
+    pub fn from_config<C: Serialize + ?Sized>(config: &C) -> Result<Self, ValueError> {
+        to_value(config)
+    }
+
+    /// Deserialize ourselves into a @C, like when loading a file. Strings aren't converted to
+    /// the booleans or numbers @C expects, unlike the ones of XML files when loading them.
+    pub fn into_config<C: DeserializeOwned>(self) -> Result<C, ValueError> {
+        from_value(self, &|_| false)
+    }
+
+    /// Write ourselves using @format
+    pub fn to_config_string(&self, format: ConfigFormat) -> Result<String, ConfigFileError> {
+        format
+            .serialize(self)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Build a table out of @table, recognizing the way TOML datetimes go through serde
+    pub(crate) fn table(mut table: BTreeMap<String, Value>) -> Value {
+        if table.len() == 1 {
+            if let Some(Value::String(datetime)) = table.remove(TOML_DATETIME_FIELD) {
+                return Value::Datetime(datetime);
+            }
+        }
+        Value::Table(table)
+    }
+
+    /// Deep-merge @other on top of ourselves: tables are merged key by key, anything else is
+    /// replaced
+    pub(crate) fn merge(&mut self, other: Value) {
+        match (self, other) {
+            (Value::Table(table), Value::Table(other)) => {
+                for (key, value) in other {
+                    match table.get_mut(&key) {
+                        Some(current) => current.merge(value),
+                        None => {
+                            table.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (current, other) => *current = other,
+        }
+    }
+
+    /// Store @v as an [`Value::Integer`] whenever it fits
+    pub(crate) fn from_u64(v: u64) -> Value {
+        i64::try_from(v).map_or(Value::Unsigned(v), Value::Integer)
+    }
+
+    /// Set the value located at @path, creating the intermediate tables as needed
+    pub(crate) fn set_path<S: AsRef<str>>(&mut self, path: &[S], value: Value) {
+        match path.split_first() {
+            None => *self = value,
+            Some((key, rest)) => {
+                if !matches!(self, Value::Table(_)) {
+                    *self = Value::Table(BTreeMap::new());
+                }
+                if let Value::Table(table) = self {
+                    table
+                        .entry(key.as_ref().to_string())
+                        .or_insert(Value::Null)
+                        .set_path(rest, value);
+                }
+            }
+        }
+    }
+
+    /// Set the value located at @segments like [`Value::set_path`], array elements being
+    /// replaced in place, or appended when their index is the length of the array
+    pub(crate) fn set_segments(
+        &mut self,
+        segments: &[Segment<'_>],
+        value: Value,
+    ) -> Result<(), String> {
+        match segments.split_first() {
+            None => *self = value,
+            Some((Segment::Key(key), rest)) => {
+                if !matches!(self, Value::Table(_)) {
+                    *self = Value::Table(BTreeMap::new());
+                }
+                if let Value::Table(table) = self {
+                    table
+                        .entry(key.to_string())
+                        .or_insert(Value::Null)
+                        .set_segments(rest, value)?;
+                }
+            }
+            Some((Segment::Index(index), rest)) => {
+                if !matches!(self, Value::Array(_)) {
+                    *self = Value::Array(Vec::new());
+                }
+                if let Value::Array(array) = self {
+                    let len = array.len();
+                    if *index == len {
+                        array.push(Value::Null);
+                    }
+                    array
+                        .get_mut(*index)
+                        .ok_or_else(|| {
+                            format!("index {} is out of bounds for {} elements", index, len)
+                        })?
+                        .set_segments(rest, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a value given as a plain string, such as an environment variable.
+    ///
+    /// Lists surrounded with brackets, whose elements are separated with commas, are recognized.
+    /// Elements containing commas can be quoted, like `["a,b", c]`, and lists can be nested, like
+    /// `[[1, 2], [3]]`.
+    /// Anything else is kept as a string, with surrounding quotes removed, which is converted
+    /// to a boolean or a number only if the target type expects one.
+    pub(crate) fn parse_override(s: &str) -> Value {
+        let s = s.trim();
+        match s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Some(list) if list.trim().is_empty() => Value::Array(Vec::new()),
+            Some(list) => Value::Array(
+                split_list(list)
+                    .into_iter()
+                    .map(Value::parse_override)
+                    .collect(),
+            ),
+            None => Value::String(unquote(s).to_string()),
+        }
+    }
+
+    fn unexpected(&self) -> de::Unexpected<'_> {
+        match self {
+            Value::Null => de::Unexpected::Unit,
+            Value::Bool(b) => de::Unexpected::Bool(*b),
+            Value::Integer(i) => de::Unexpected::Signed(*i),
+            Value::Unsigned(u) => de::Unexpected::Unsigned(*u),
+            Value::Float(f) => de::Unexpected::Float(*f),
+            Value::String(s) | Value::Datetime(s) => de::Unexpected::Str(s),
+            Value::Array(_) => de::Unexpected::Seq,
+            Value::Table(_) => de::Unexpected::Map,
+        }
+    }
+
+    fn into_key(self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Bool(b) => b.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Unsigned(u) => u.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::String(s) | Value::Datetime(s) => s,
+            Value::Array(_) | Value::Table(_) => String::new(),
+        }
+    }
+}
+
+pub(crate) enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Split a key path such as `servers[0].host` into its segments
+pub(crate) fn parse_key_path(key_path: &str) -> Option<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    for part in key_path.split('.') {
+        let (key, mut indices) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if key.is_empty() && indices.is_empty() {
+            return None;
+        }
+        if !key.is_empty() {
+            segments.push(Segment::Key(key));
+        }
+        while let Some(index) = indices.strip_prefix('[') {
+            let end = index.find(']')?;
+            segments.push(Segment::Index(index[..end].parse().ok()?));
+            indices = &index[end + 1..];
+        }
+        if !indices.is_empty() {
+            return None;
+        }
+    }
+    Some(segments)
+}
+
+/// Split @list on the commas which are neither quoted nor inside a nested list
+fn split_list(list: &str) -> Vec<&str> {
+    let mut elements = Vec::new();
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, c) in list.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '[') => depth += 1,
+            (None, ']') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => {
+                elements.push(&list[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    elements.push(&list[start..]);
+    elements
+}
+
+fn unquote(s: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(s) = s.strip_prefix(quote).and_then(|s| s.strip_suffix(quote)) {
+            return s;
+        }
+    }
+    s
+}
+
+/// There was an error while deserializing a configuration document into the requested type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueError {
+    message: String,
+    key_path: Option<String>,
+}
+
+impl ValueError {
+    /// The path of the key whose value failed to deserialize, such as `inner.answer` or
+    /// `tags[1]`
+    pub fn key_path(&self) -> Option<&str> {
+        self.key_path.as_deref()
+    }
+
+    /// Hide @secrets from the message, which can quote the offending value
+    pub(crate) fn redact(mut self, secrets: &[String]) -> Self {
+        self.message = redact(&self.message, secrets);
+        self
+    }
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(key_path) = &self.key_path {
+            write!(f, "{}: ", key_path)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+impl de::Error for ValueError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self {
+            message: msg.to_string(),
+            key_path: None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("any configuration value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(Value::from_u64(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Value, E> {
+        String::from_utf8(v.to_vec())
+            .map(Value::String)
+            .map_err(|_| E::invalid_value(de::Unexpected::Bytes(v), &self))
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Value, D::Error> {
+        Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let xml = parsing_xml();
+        let mut table = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry::<Value, Value>()? {
+            let key = key.into_key();
+            if !xml {
+                table.insert(key, value);
+                continue;
+            }
+            // XML represents lists as repeated elements
+            match table.remove(&key) {
+                Some(Value::Array(mut values)) => {
+                    values.push(value);
+                    table.insert(key, Value::Array(values));
+                }
+                Some(previous) => {
+                    table.insert(key, Value::Array(vec![previous, value]));
+                }
+                None => {
+                    table.insert(key, value);
+                }
+            }
+        }
+        // XML elements containing only text end up as {"$value": text}
+        if xml && table.len() == 1 {
+            if let Some(value) = table.remove("$value") {
+                return Ok(value);
+            }
+        }
+        Ok(Value::table(table))
+    }
+}
+
+thread_local! {
+    #[cfg(feature = "xml")]
+    static PARSING_XML: Cell<bool> = Cell::new(false);
+    static KEEPING_DATETIMES: Cell<bool> = Cell::new(false);
+    #[cfg(feature = "toml")]
+    static WRITING_TOML: Cell<bool> = Cell::new(false);
+}
+
+/// Run @f with @flag set, since serde doesn't tell visitors and serializable types which format
+/// they are used with
+fn with_flag<T, F: FnOnce() -> T>(flag: &'static LocalKey<Cell<bool>>, f: F) -> T {
+    struct Reset(&'static LocalKey<Cell<bool>>, bool);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            self.0.with(|flag| flag.set(self.1));
+        }
+    }
+
+    let _reset = Reset(flag, flag.with(|flag| flag.replace(true)));
+    f()
+}
+
+/// Run @f with the XML workarounds of [`Value`]'s deserialization enabled
+#[cfg(feature = "xml")]
+pub(crate) fn with_xml<T, F: FnOnce() -> T>(f: F) -> T {
+    with_flag(&PARSING_XML, f)
+}
+
+/// Whether we are called from [`with_xml`]
+fn parsing_xml() -> bool {
+    #[cfg(feature = "xml")]
+    return PARSING_XML.with(Cell::get);
+    #[cfg(not(feature = "xml"))]
+    false
+}
+
+/// Run @f with [`Value::Datetime`] serialized as a TOML datetime instead of a string, for the
+/// serializers which support them: TOML's and [`Value`]'s own
+pub(crate) fn with_datetimes<T, F: FnOnce() -> T>(f: F) -> T {
+    with_flag(&KEEPING_DATETIMES, f)
+}
+
+/// Whether we are called from [`with_datetimes`]
+fn keeping_datetimes() -> bool {
+    KEEPING_DATETIMES.with(Cell::get)
+}
+
+/// Run @f with [`Value`] serialized the way TOML requires: datetimes kept, and the plain values
+/// of each table written before its tables
+#[cfg(feature = "toml")]
+pub(crate) fn with_toml<T, F: FnOnce() -> T>(f: F) -> T {
+    with_flag(&WRITING_TOML, || with_datetimes(f))
+}
+
+/// Whether we are called from [`with_toml`]
+fn writing_toml() -> bool {
+    #[cfg(feature = "toml")]
+    return WRITING_TOML.with(Cell::get);
+    #[cfg(not(feature = "toml"))]
+    false
+}
+
+/// Deserialize a @C out of @value.
+///
+/// The values for whose key path @untyped returns true come from formats which only have
+/// strings, such as XML, and are converted to the types @C expects: strings to booleans or
+/// numbers, and single values to lists of one element.
+pub(crate) fn from_value<C: DeserializeOwned>(
+    value: Value,
+    untyped: &dyn Fn(&str) -> bool,
+) -> Result<C, ValueError> {
+    deserialize_tracked(ValueDeserializer {
+        value,
+        key_path: String::new(),
+        untyped,
+    })
+    .map_err(|(err, key_path)| ValueError { key_path, ..err })
+}
+
+/// Deserialize a @C out of @value like [`from_value`], also returning the paths of the keys @C
+/// ignored
+pub(crate) fn from_value_reporting_unknown<C: DeserializeOwned>(
+    value: Value,
+    untyped: &dyn Fn(&str) -> bool,
+) -> Result<(C, Vec<String>), ValueError> {
+    let mut unknown = Vec::new();
+    let mut report = |path: serde_ignored::Path<'_>| unknown.push(ignored_key_path(&path));
+    let deserializer = ValueDeserializer {
+        value,
+        key_path: String::new(),
+        untyped,
+    };
+    let config = deserialize_tracked(serde_ignored::Deserializer::new(deserializer, &mut report))
+        .map_err(|(err, key_path)| ValueError { key_path, ..err })?;
+    Ok((config, unknown))
+}
+
+/// Render @path like the key paths of errors, such as `inner.answer` or `tags[1]`
+fn ignored_key_path(path: &serde_ignored::Path<'_>) -> String {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => join(&ignored_key_path(parent), &format!("[{}]", index)),
+        Path::Map { parent, key } => join(&ignored_key_path(parent), key),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => ignored_key_path(parent),
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+                let value = match self.value {
+                    Value::String(s) if (self.untyped)(&self.key_path) => {
+                        s.parse::<Value>().unwrap_or(Value::String(s))
+                    }
+                    value => value,
+                };
+                ValueDeserializer { value, ..self }.deserialize_any(visitor)
+            }
+        )*
+    };
+}
+
+impl std::str::FromStr for Value {
+    type Err = ();
+
+    /// Parse a scalar out of a plain string
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => s
+                .parse()
+                .map(Value::Integer)
+                .or_else(|_| s.parse().map(Value::Unsigned))
+                .or_else(|_| s.parse().map(Value::Float))
+                .map_err(|_| ()),
+        }
+    }
+}
+
+/// Deserializer of the @value located at @key_path in the document being deserialized
+struct ValueDeserializer<'a> {
+    value: Value,
+    key_path: String,
+    untyped: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> ValueDeserializer<'a> {
+    /// Deserializer of @value, located at @key inside ours
+    fn child(&self, key: &str, value: Value) -> Self {
+        Self {
+            value,
+            key_path: join(&self.key_path, key),
+            untyped: self.untyped,
+        }
+    }
+
+    fn seq(self, values: Vec<Value>) -> SeqDeserializer<'a> {
+        SeqDeserializer {
+            parent: Self {
+                value: Value::Null,
+                ..self
+            },
+            iter: values.into_iter().enumerate(),
+        }
+    }
+}
+
+impl<'de, 'a> Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, ValueError> {
+        match self.value {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Unsigned(u) => visitor.visit_u64(u),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) | Value::Datetime(s) => visitor.visit_string(s),
+            Value::Array(ref mut values) => {
+                let values = std::mem::take(values);
+                visitor.visit_seq(self.seq(values))
+            }
+            Value::Table(ref mut table) => {
+                let iter = std::mem::take(table).into_iter();
+                visitor.visit_map(MapDeserializer {
+                    parent: self,
+                    iter,
+                    value: None,
+                })
+            }
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        match self.value {
+            // Give TOML datetimes back the way they came in
+            Value::Datetime(ref mut datetime) if name == TOML_DATETIME_NAME => {
+                let datetime = Value::String(std::mem::take(datetime));
+                let iter = BTreeMap::from([(TOML_DATETIME_FIELD.to_string(), datetime)]);
+                visitor.visit_map(MapDeserializer {
+                    parent: self,
+                    iter: iter.into_iter(),
+                    value: None,
+                })
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, ValueError> {
+        match self.value {
+            Value::Array(ref mut values) => {
+                let values = std::mem::take(values);
+                visitor.visit_seq(self.seq(values))
+            }
+            // XML lists with a single element can't be told apart from a plain value
+            _ if (self.untyped)(&self.key_path) => {
+                let values = match std::mem::replace(&mut self.value, Value::Null) {
+                    Value::Null => Vec::new(),
+                    value => vec![value],
+                };
+                visitor.visit_seq(self.seq(values))
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        mut self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        match self.value {
+            Value::String(ref mut variant) => {
+                let variant = std::mem::take(variant);
+                visitor.visit_enum(EnumDeserializer {
+                    variant,
+                    value: None,
+                })
+            }
+            Value::Table(ref mut table) if table.len() == 1 => {
+                let (variant, value) = std::mem::take(table).into_iter().next().unwrap();
+                let value = self.child(&variant, value);
+                visitor.visit_enum(EnumDeserializer {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            value => Err(de::Error::invalid_type(value.unexpected(), &"enum")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct map identifier ignored_any
+    }
+}
+
+struct SeqDeserializer<'a> {
+    parent: ValueDeserializer<'a>,
+    iter: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for SeqDeserializer<'a> {
+    type Error = ValueError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ValueError> {
+        match self.iter.next() {
+            Some((index, value)) => {
+                let index = format!("[{}]", index);
+                seed.deserialize(self.parent.child(&index, value)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapDeserializer<'a> {
+    parent: ValueDeserializer<'a>,
+    iter: std::collections::btree_map::IntoIter<String, Value>,
+    value: Option<ValueDeserializer<'a>>,
+}
+
+impl<'de, 'a> MapAccess<'de> for MapDeserializer<'a> {
+    type Error = ValueError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ValueError> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(self.parent.child(&key, value));
+                // Keys are strings in every format, they are converted like untyped values
+                let key = ValueDeserializer {
+                    untyped: &|_| true,
+                    ..self.parent.child(&key, Value::String(key.clone()))
+                };
+                seed.deserialize(key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ValueError> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumDeserializer<'a> {
+    variant: String,
+    value: Option<ValueDeserializer<'a>>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for EnumDeserializer<'a> {
+    type Error = ValueError;
+    type Variant = VariantDeserializer<'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantDeserializer<'a>), ValueError> {
+        let variant: StringDeserializer<ValueError> = self.variant.into_deserializer();
+        seed.deserialize(variant)
+            .map(|variant| (variant, VariantDeserializer(self.value)))
+    }
+}
+
+struct VariantDeserializer<'a>(Option<ValueDeserializer<'a>>);
+
+impl<'de, 'a> VariantAccess<'de> for VariantDeserializer<'a> {
+    type Error = ValueError;
+
+    fn unit_variant(self) -> Result<(), ValueError> {
+        match self.0.map(|value| value.value) {
+            None | Some(Value::Null) => Ok(()),
+            Some(value) => Err(de::Error::invalid_type(value.unexpected(), &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, ValueError> {
+        match self.0 {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        match self.0 {
+            Some(value) => value.deserialize_seq(visitor),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        match self.0 {
+            Some(value) => value.deserialize_any(visitor),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(entries: &[(&str, Value)]) -> Value {
+        Value::Table(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut value = table(&[
+            ("host", Value::String("localhost".to_string())),
+            ("port", Value::Integer(80)),
+            ("inner", table(&[("answer", Value::Integer(41))])),
+        ]);
+        value.merge(table(&[
+            ("port", Value::Integer(443)),
+            ("inner", table(&[("question", Value::Null)])),
+        ]));
+        assert_eq!(
+            value,
+            table(&[
+                ("host", Value::String("localhost".to_string())),
+                ("port", Value::Integer(443)),
+                (
+                    "inner",
+                    table(&[("answer", Value::Integer(41)), ("question", Value::Null)])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_set_path() {
+        let mut value = table(&[("inner", Value::Integer(41))]);
+        value.set_path(&["inner", "answer"], Value::Integer(42));
+        value.set_path(&["host"], Value::String("example.com".to_string()));
+        assert_eq!(
+            value,
+            table(&[
+                ("host", Value::String("example.com".to_string())),
+                ("inner", table(&[("answer", Value::Integer(42))])),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_set_segments() {
+        let mut value = table(&[("tags", Value::Array(vec![Value::Integer(1)]))]);
+        let set = |value: &mut Value, key_path, new| {
+            value.set_segments(&parse_key_path(key_path).unwrap(), new)
+        };
+        set(&mut value, "tags[0]", Value::Integer(2)).unwrap();
+        set(&mut value, "tags[1]", Value::Integer(3)).unwrap();
+        set(
+            &mut value,
+            "servers[0].host",
+            Value::String("a".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            set(&mut value, "tags[3]", Value::Integer(4)),
+            Err("index 3 is out of bounds for 2 elements".to_string())
+        );
+        assert_eq!(
+            value,
+            table(&[
+                (
+                    "servers",
+                    Value::Array(vec![table(&[("host", Value::String("a".to_string()))])])
+                ),
+                (
+                    "tags",
+                    Value::Array(vec![Value::Integer(2), Value::Integer(3)])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_override() {
+        assert_eq!(Value::parse_override("true"), string("true"));
+        assert_eq!(Value::parse_override(" 007 "), string("007"));
+        assert_eq!(Value::parse_override("\"42\""), string("42"));
+        assert_eq!(Value::parse_override("[]"), Value::Array(Vec::new()));
+        assert_eq!(
+            Value::parse_override("[1, 'two']"),
+            Value::Array(vec![string("1"), string("two")])
+        );
+        assert_eq!(
+            Value::parse_override(r#"["a,b", 'c, "d"', e]"#),
+            Value::Array(vec![string("a,b"), string("c, \"d\""), string("e")])
+        );
+        assert_eq!(
+            Value::parse_override("[[1,2],[3], \"[4,\"]"),
+            Value::Array(vec![
+                Value::Array(vec![string("1"), string("2")]),
+                Value::Array(vec![string("3")]),
+                string("[4,"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parsed_scalars() {
+        let value = table(&[
+            ("port", Value::String("443".to_string())),
+            ("enabled", Value::String("true".to_string())),
+            ("tags", Value::String("single".to_string())),
+        ]);
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Parsed {
+            port: u16,
+            enabled: bool,
+            tags: Vec<String>,
+        }
+
+        assert_eq!(
+            from_value::<Parsed>(value.clone(), &|_| true).unwrap(),
+            Parsed {
+                port: 443,
+                enabled: true,
+                tags: vec!["single".to_string()],
+            }
+        );
+        assert!(from_value::<Parsed>(value, &|key_path| key_path != "port").is_err());
+    }
+
+    #[test]
+    fn test_unsigned() {
+        assert_eq!(
+            "18446744073709551615".parse::<Value>(),
+            Ok(Value::Unsigned(u64::MAX))
+        );
+        assert_eq!(Value::from_u64(42), Value::Integer(42));
+        let value = Value::from_u64(u64::MAX);
+        assert_eq!(from_value::<u64>(value, &|_| false).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    #[cfg(all(feature = "json", feature = "xml"))]
+    fn test_repeated_keys() {
+        let value: Value =
+            serde_json::from_str(r#"{ "a": 1, "a": 2, "b": { "$value": 3 } }"#).unwrap();
+        assert_eq!(
+            value,
+            table(&[
+                ("a", Value::Integer(2)),
+                ("b", table(&[("$value", Value::Integer(3))])),
+            ])
+        );
+        let value: Value =
+            with_xml(|| serde_xml_rs::from_str("<config><a>1</a><a>2</a><b>3</b></config>"))
+                .unwrap();
+        assert_eq!(
+            value,
+            table(&[
+                ("a", Value::Array(vec![string("1"), string("2")])),
+                ("b", string("3")),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_get_set() {
+        let mut value = table(&[
+            ("inner", table(&[("answer", Value::Integer(41))])),
+            (
+                "servers",
+                Value::Array(vec![table(&[("host", string("a"))])]),
+            ),
+        ]);
+        assert_eq!(value.get("inner.answer"), Some(&Value::Integer(41)));
+        assert_eq!(value.get("servers[0].host"), Some(&string("a")));
+        assert_eq!(value.get("servers[1].host"), None);
+        assert_eq!(value.get("inner.answer.more"), None);
+
+        *value.get_mut("inner.answer").unwrap() = Value::Integer(42);
+        value.set("servers[1].host", string("b")).unwrap();
+        value.set("inner.question", Value::Null).unwrap();
+        assert_eq!(value.get("inner.answer"), Some(&Value::Integer(42)));
+        assert_eq!(value.get("servers[1].host"), Some(&string("b")));
+        assert_eq!(value.get("inner.question"), Some(&Value::Null));
+
+        let err = value.set("servers[3].host", string("d")).unwrap_err();
+        assert_eq!(err.key_path(), Some("servers[3].host"));
+        assert_eq!(
+            err.to_string(),
+            "servers[3].host: index 3 is out of bounds for 2 elements"
+        );
+        assert!(value.set("servers[x]", Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Config {
+            host: String,
+            ports: Vec<u16>,
+        }
+
+        let config = Config {
+            host: "localhost".to_string(),
+            ports: vec![80, 443],
+        };
+        let value = Value::from_config(&config).unwrap();
+        assert_eq!(
+            value,
+            table(&[
+                ("host", string("localhost")),
+                (
+                    "ports",
+                    Value::Array(vec![Value::Integer(80), Value::Integer(443)])
+                ),
+            ])
+        );
+        assert_eq!(value.into_config::<Config>().unwrap(), config);
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "json"))]
+    fn test_datetime() {
+        #[derive(Debug, Deserialize, Serialize)]
+        struct Config {
+            created: toml_crate::value::Datetime,
+            updated: String,
+        }
+
+        let contents = "created = 1979-05-27T07:32:00Z\nupdated = 1979-05-27\n";
+        let value: Value = ConfigFormat::Toml.deserialize(contents).unwrap();
+        let datetime = Value::Datetime("1979-05-27T07:32:00Z".to_string());
+        assert_eq!(value.get("created"), Some(&datetime));
+        assert_eq!(
+            value.get("updated"),
+            Some(&Value::Datetime("1979-05-27".to_string()))
+        );
+
+        let config: Config = value.clone().into_config().unwrap();
+        assert_eq!(config.created.to_string(), "1979-05-27T07:32:00Z");
+        assert_eq!(config.updated, "1979-05-27");
+        assert_eq!(
+            Value::from_config(&config).unwrap().get("created"),
+            Some(&datetime)
+        );
+
+        assert_eq!(
+            value.to_config_string(ConfigFormat::Json).unwrap(),
+            "{\n  \"created\": \"1979-05-27T07:32:00Z\",\n  \"updated\": \"1979-05-27\"\n}"
+        );
+        assert_eq!(
+            value.to_config_string(ConfigFormat::Toml).unwrap(),
+            contents
+        );
+        let nested = Value::from_config(&table(&[("value", value)])).unwrap();
+        assert_eq!(nested.get("value.created"), Some(&datetime));
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "json"))]
+    fn test_toml_order() {
+        let value = table(&[
+            ("inner", table(&[("answer", Value::Integer(42))])),
+            ("port", Value::Integer(443)),
+            (
+                "servers",
+                Value::Array(vec![table(&[("host", string("example.com"))])]),
+            ),
+            ("tags", Value::Array(vec![string("a")])),
+        ]);
+        let contents = value.to_config_string(ConfigFormat::Toml).unwrap();
+        assert_eq!(
+            contents,
+            "port = 443\ntags = [\"a\"]\n\n[[servers]]\nhost = \"example.com\"\n\n[inner]\nanswer = 42\n"
+        );
+        assert_eq!(
+            ConfigFormat::Toml.deserialize::<Value>(&contents).unwrap(),
+            value
+        );
+        // The other formats keep the alphabetical order
+        let contents = value.to_config_string(ConfigFormat::Json).unwrap();
+        assert!(contents.starts_with("{\n  \"inner\""));
+    }
+}
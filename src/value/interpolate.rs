@@ -0,0 +1,298 @@
+//! Resolution of `${...}` references inside the strings of a [`Value`].
+
+use super::{parse_key_path, Segment, Value};
+use crate::{
+    key_path::{join, KeyPathMap},
+    ConfigFileError,
+};
+use std::collections::BTreeMap;
+
+/// Resolve the references to other keys (`${key.path}`) and to environment variables
+/// (`${env:NAME}` or `${env:NAME:-default}`) found in the strings of @root, looking variables up
+/// with @var. `$${` is kept as a literal `${`.
+///
+/// The strings built out of references are flagged in @untyped, so that `${env:PORT}` can be
+/// converted to a number, while a lone reference to another key keeps its flags.
+pub(crate) fn interpolate<F>(
+    root: &Value,
+    var: F,
+    untyped: &mut KeyPathMap<bool>,
+) -> Result<Value, ConfigFileError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    Interpolator {
+        root,
+        var,
+        untyped,
+        resolving: Vec::new(),
+    }
+    .resolve(root, String::new())
+}
+
+struct Interpolator<'a, F> {
+    root: &'a Value,
+    var: F,
+    untyped: &'a mut KeyPathMap<bool>,
+    /// The strings being resolved, to detect cycles
+    resolving: Vec<String>,
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Reference(&'a str),
+}
+
+impl<'a, F: Fn(&str) -> Option<String>> Interpolator<'a, F> {
+    fn resolve(&mut self, value: &Value, key_path: String) -> Result<Value, ConfigFileError> {
+        Ok(match value {
+            Value::String(s) => self.resolve_string(s, key_path)?,
+            Value::Array(array) => Value::Array(
+                array
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        self.resolve(value, join(&key_path, &format!("[{}]", index)))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Table(table) => Value::Table(
+                table
+                    .iter()
+                    .map(|(key, value)| {
+                        Ok((key.clone(), self.resolve(value, join(&key_path, key))?))
+                    })
+                    .collect::<Result<BTreeMap<_, _>, ConfigFileError>>()?,
+            ),
+            value => value.clone(),
+        })
+    }
+
+    fn resolve_string(&mut self, s: &str, key_path: String) -> Result<Value, ConfigFileError> {
+        let error = |reason: String| ConfigFileError::Interpolation {
+            key_path: key_path.clone(),
+            reason,
+        };
+        let parts = parse(s).map_err(|reason| error(reason.to_string()))?;
+        if !parts.iter().any(|part| matches!(part, Part::Reference(_))) {
+            return Ok(Value::String(parts_to_string(&parts)));
+        }
+        if self.resolving.contains(&key_path) {
+            let mut cycle = self.resolving.clone();
+            cycle.push(key_path.clone());
+            return Err(error(format!("reference cycle: {}", cycle.join(" -> "))));
+        }
+        self.resolving.push(key_path.clone());
+        let res = match parts.as_slice() {
+            // A lone reference keeps the type of what it refers to
+            [Part::Reference(reference)] => {
+                self.resolve_reference(reference).map(|(value, flagged)| {
+                    self.untyped
+                        .record(key_path.clone(), &value, &mut |_| flagged);
+                    value
+                })
+            }
+            parts => parts
+                .iter()
+                .map(|part| match part {
+                    Part::Text(text) => Ok(text.to_string()),
+                    Part::Reference(reference) => match self.resolve_reference(reference)?.0 {
+                        Value::Array(_) | Value::Table(_) => Err(error(format!(
+                            "${{{}}} can't be embedded in a string",
+                            reference
+                        ))),
+                        value => Ok(value.into_key()),
+                    },
+                })
+                .collect::<Result<String, _>>()
+                .map(|s| {
+                    self.untyped.insert(key_path.clone(), true);
+                    Value::String(s)
+                }),
+        };
+        self.resolving.pop();
+        res
+    }
+
+    /// The value @reference refers to, and whether it is flagged as untyped
+    fn resolve_reference(&mut self, reference: &str) -> Result<(Value, bool), ConfigFileError> {
+        let error = |reason: String| ConfigFileError::Interpolation {
+            key_path: self.resolving.last().cloned().unwrap_or_default(),
+            reason,
+        };
+        if let Some(var) = reference.strip_prefix("env:") {
+            let (name, default) = match var.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (var, None),
+            };
+            let value = match ((self.var)(name), default) {
+                (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+                (Some(value), _) => value,
+                (None, Some(default)) => default.to_string(),
+                (None, None) => {
+                    return Err(error(format!("environment variable {} is not set", name)))
+                }
+            };
+            return Ok((Value::String(value), true));
+        }
+        let segments = parse_key_path(reference)
+            .ok_or_else(|| error(format!("invalid reference ${{{}}}", reference)))?;
+        let mut value = self.root;
+        let mut key_path = String::new();
+        for segment in &segments {
+            value = match (value, segment) {
+                (Value::Table(table), Segment::Key(key)) => {
+                    key_path = join(&key_path, key);
+                    table.get(*key)
+                }
+                (Value::Array(array), Segment::Index(index)) => {
+                    key_path = join(&key_path, &format!("[{}]", index));
+                    array.get(*index)
+                }
+                _ => None,
+            }
+            .ok_or_else(|| error(format!("${{{}}} doesn't exist", reference)))?;
+        }
+        let value = self.resolve(value, key_path.clone())?;
+        Ok((value, self.untyped.is_flagged(&key_path)))
+    }
+}
+
+/// Split @s into text and references, unescaping `$${`
+fn parse(s: &str) -> Result<Vec<Part<'_>>, &'static str> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if let Some(text) = rest[..start].strip_suffix('$') {
+            parts.extend([Part::Text(text), Part::Text("${")]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+        parts.push(Part::Text(&rest[..start]));
+        let end = rest[start..].find('}').ok_or("unterminated reference")? + start;
+        let reference = rest[start + 2..end].trim();
+        if reference.is_empty() {
+            return Err("empty reference");
+        }
+        parts.push(Part::Reference(reference));
+        rest = &rest[end + 1..];
+    }
+    parts.push(Part::Text(rest));
+    parts.retain(|part| !matches!(part, Part::Text("")));
+    Ok(parts)
+}
+
+fn parts_to_string(parts: &[Part<'_>]) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Text(text) => *text,
+            Part::Reference(_) => "",
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(entries: &[(&str, Value)]) -> Value {
+        Value::Table(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn var(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/user".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let root = table(&[
+            ("data_dir", string("${env:HOME}/data")),
+            ("cache", string("${data_dir}/cache")),
+            ("port", string("${env:PORT:-8080}")),
+            ("empty", string("${env:EMPTY:-default}")),
+            (
+                "ports",
+                Value::Array(vec![Value::Integer(80), Value::Integer(443)]),
+            ),
+            ("tls_port", string("${ports[1]}")),
+            (
+                "url",
+                string("http://localhost:${ports[0]}/$${not_a_reference}"),
+            ),
+            ("inner", table(&[("ports", string("${ports}"))])),
+        ]);
+        let expected = table(&[
+            ("data_dir", string("/home/user/data")),
+            ("cache", string("/home/user/data/cache")),
+            ("port", string("8080")),
+            ("empty", string("default")),
+            (
+                "ports",
+                Value::Array(vec![Value::Integer(80), Value::Integer(443)]),
+            ),
+            ("tls_port", Value::Integer(443)),
+            ("url", string("http://localhost:80/${not_a_reference}")),
+            (
+                "inner",
+                table(&[(
+                    "ports",
+                    Value::Array(vec![Value::Integer(80), Value::Integer(443)]),
+                )]),
+            ),
+        ]);
+        let mut untyped = KeyPathMap::default();
+        assert_eq!(interpolate(&root, var, &mut untyped).unwrap(), expected);
+        assert!(untyped.is_flagged("port"));
+        assert!(untyped.is_flagged("cache"));
+        assert!(untyped.is_flagged("url"));
+        assert!(!untyped.is_flagged("tls_port"));
+        assert!(!untyped.is_flagged("inner.ports[0]"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |root: Value| match interpolate(&root, var, &mut KeyPathMap::default()) {
+            Err(ConfigFileError::Interpolation { key_path, reason }) => (key_path, reason),
+            res => panic!("unexpected result: {:?}", res),
+        };
+        assert_eq!(
+            error(table(&[
+                ("a", string("${b}")),
+                ("b", table(&[("c", string("x${a}"))])),
+            ])),
+            (
+                "a".to_string(),
+                "reference cycle: a -> b.c -> a".to_string()
+            )
+        );
+        assert_eq!(
+            error(table(&[("a", string("${env:MISSING}"))])),
+            (
+                "a".to_string(),
+                "environment variable MISSING is not set".to_string()
+            )
+        );
+        assert_eq!(
+            error(table(&[("a", string("${b.c}")), ("b", Value::Integer(1))])),
+            ("a".to_string(), "${b.c} doesn't exist".to_string())
+        );
+        assert_eq!(
+            error(table(&[("a", Value::Array(vec![string("${b")]))])),
+            ("a[0]".to_string(), "unterminated reference".to_string())
+        );
+    }
+}
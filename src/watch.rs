@@ -0,0 +1,246 @@
+use crate::{ConfigFileError, FromConfigFile};
+use serde::de::DeserializeOwned;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// Watch a configuration file and reload it when it changes.
+///
+/// The file is polled for modifications, comparing its size, modification time and, on unix,
+/// inode, so that editors saving through a temporary file renamed over the original one are
+/// handled too. The last successfully parsed value is kept when the new contents fail to parse.
+///
+/// ```rust,no_run
+/// use config_file::ConfigWatcher;
+/// use serde::Deserialize;
+/// use std::time::Duration;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     host: String,
+/// }
+///
+/// let watcher = ConfigWatcher::<Config>::new("/etc/myconfig.toml").unwrap();
+/// println!("host is {}", watcher.current().host);
+/// let handle = watcher.spawn(
+///     Duration::from_secs(1),
+///     |config| println!("host is now {}", config.host),
+///     |err| eprintln!("ignoring invalid configuration: {}", err),
+/// );
+/// // ...
+/// handle.stop();
+/// ```
+#[derive(Debug)]
+pub struct ConfigWatcher<C> {
+    path: PathBuf,
+    current: C,
+    stamp: Option<Stamp>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    inode: (u64, u64),
+    #[cfg(unix)]
+    changed: (i64, i64),
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Option<Self>> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            // Editors may remove the file right before renaming the new one in its place
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        Ok(Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            inode: (metadata.dev(), metadata.ino()),
+            #[cfg(unix)]
+            changed: (metadata.ctime(), metadata.ctime_nsec()),
+        }))
+    }
+}
+
+impl<C: DeserializeOwned> ConfigWatcher<C> {
+    /// Load the configuration file located at @path and start watching it
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, ConfigFileError> {
+        let path = path.into();
+        let stamp = Stamp::of(&path).map_err(ConfigFileError::FileAccess)?;
+        let current = C::from_config_file(&path)?;
+        Ok(Self {
+            path,
+            current,
+            stamp,
+        })
+    }
+
+    /// The last successfully loaded configuration
+    pub fn current(&self) -> &C {
+        &self.current
+    }
+
+    /// Consume the watcher, returning the last successfully loaded configuration
+    pub fn into_current(self) -> C {
+        self.current
+    }
+
+    /// Check whether the file changed since last time, and reload it if it did.
+    ///
+    /// Returns `None` if the file didn't change, the new configuration if it was successfully
+    /// reloaded, or the error which occurred, in which case the previous configuration is kept.
+    pub fn poll(&mut self) -> Option<Result<&C, ConfigFileError>> {
+        let stamp = match Stamp::of(&self.path) {
+            Ok(stamp) => stamp,
+            Err(err) => return Some(Err(ConfigFileError::FileAccess(err))),
+        };
+        if stamp.is_none() || stamp == self.stamp {
+            return None;
+        }
+        self.stamp = stamp;
+        Some(C::from_config_file(&self.path).map(move |config| {
+            self.current = config;
+            &self.current
+        }))
+    }
+
+    /// Poll the file every @interval in a background thread, calling @on_change with each newly
+    /// loaded configuration and @on_error with each error.
+    pub fn spawn<F, E>(
+        mut self,
+        interval: Duration,
+        mut on_change: F,
+        mut on_error: E,
+    ) -> WatchHandle
+    where
+        C: Send + 'static,
+        F: FnMut(&C) + Send + 'static,
+        E: FnMut(ConfigFileError) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                match self.poll() {
+                    Some(Ok(config)) => on_change(config),
+                    Some(Err(err)) => on_error(err),
+                    None => {}
+                }
+            }
+        });
+        WatchHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Handle on a [`ConfigWatcher`] running in a background thread.
+/// The watcher is stopped when the handle is dropped.
+#[derive(Debug)]
+pub struct WatchHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchHandle {
+    /// Stop watching and wait for the background thread to exit
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(all(test, feature = "toml"))]
+mod test {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq)]
+    struct TestConfig {
+        answer: u8,
+    }
+
+    #[test]
+    fn test_poll() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "answer = 41\n").unwrap();
+        let mut watcher = ConfigWatcher::<TestConfig>::new(&path).unwrap();
+        assert_eq!(watcher.current().answer, 41);
+        assert!(watcher.poll().is_none());
+
+        fs::write(&path, "answer = 42 \n").unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap().answer, 42);
+        assert!(watcher.poll().is_none());
+
+        fs::write(&path, "answer = \"many\"\n").unwrap();
+        assert!(matches!(
+            watcher.poll(),
+            Some(Err(ConfigFileError::Parse(_)))
+        ));
+        assert_eq!(watcher.current().answer, 42);
+        assert!(watcher.poll().is_none());
+    }
+
+    #[test]
+    fn test_rename_and_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let tmp = dir.path().join("config.toml.swp");
+        fs::write(&path, "answer = 41\n").unwrap();
+        let mut watcher = ConfigWatcher::<TestConfig>::new(&path).unwrap();
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.poll().is_none());
+        fs::write(&tmp, "answer = 42\n").unwrap();
+        fs::rename(&tmp, &path).unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap().answer, 42);
+        assert_eq!(watcher.into_current().answer, 42);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "answer = 41\n").unwrap();
+        let (changes, changed) = mpsc::channel();
+        let (errors, failed) = mpsc::channel();
+        let handle = ConfigWatcher::<TestConfig>::new(&path).unwrap().spawn(
+            Duration::from_millis(10),
+            move |config| changes.send(config.clone()).unwrap(),
+            move |err| errors.send(err.to_string()).unwrap(),
+        );
+
+        fs::write(&path, "answer = \"many\"\n").unwrap();
+        assert!(failed.recv_timeout(Duration::from_secs(5)).is_ok());
+        fs::write(&path, "answer = 42\n").unwrap();
+        assert_eq!(
+            changed.recv_timeout(Duration::from_secs(5)).unwrap(),
+            TestConfig { answer: 42 }
+        );
+        handle.stop();
+    }
+}